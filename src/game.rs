@@ -1,14 +1,57 @@
+mod bots;
+mod classic;
+mod clock;
+mod engine;
+mod events;
+mod gravity;
+mod hints;
+mod mcts;
+mod misere;
+mod notakto;
+mod rules;
+mod series;
+mod ultimate;
+
+use std::collections::HashMap;
 use std::fmt::Display;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use rand::{distributions::Alphanumeric, Rng};
 use serde::{Deserialize, Serialize};
 use tokio::sync::watch;
 use tracing::debug;
 
+pub use clock::{Clock, TimeControl};
+pub use engine::{Analysis, Difficulty};
+pub use events::{Event, EventKind};
+pub use hints::HintRequest;
+pub use rules::{Rules, Variant};
+pub use series::{Series, SeriesGame};
+
+use engine::Outcome;
+use rules::other_team;
+
 #[derive(Debug)]
 pub struct Game {
     pub id: String,
     pub state: State,
     pub state_changes: watch::Sender<State>,
+    rules: Box<dyn Rules>,
+    /// Proves a connection speaks for a seat, and lets a player take their
+    /// seat back after losing their connection. Kept out of `State` so it is
+    /// never broadcast.
+    secrets: HashMap<PlayerID, String>,
+    /// Needed to take a seat rather than watch.
+    invite: String,
+    /// Finished games with a player who has a profile, waiting to be
+    /// recorded.
+    results: Vec<GameResult>,
+    /// Everything done to the game so far, oldest first.
+    events: Vec<Event>,
+    /// Time of the event being applied, in milliseconds since the Unix epoch.
+    now: u64,
+    /// When each seat last got a hint.
+    hints_given: HashMap<PlayerID, u64>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -16,26 +59,444 @@ pub struct State {
     pub turn: char,
     pub winner: Option<EndState>,
     pub players: Vec<Player>,
-    pub board: Vec<char>,
+    pub config: Config,
+    pub board: Board,
+    pub sub_boards: Option<SubBoards>,
+    /// Every move of the current game, oldest first.
+    pub moves: Vec<MoveRecord>,
+    /// Player waiting for their opponent to agree to take back a move.
+    pub undo_request: Option<PlayerID>,
+    /// A rematch one player proposed and the other has not answered yet.
+    pub rematch_offer: Option<Offer>,
+    /// Player who offered a draw that the other has not answered yet.
+    pub draw_offer: Option<PlayerID>,
+    /// Time left for each side, if the room has a time control.
+    pub clock: Option<Clock>,
+    /// Score of the match, if the room plays a best-of-N series.
+    pub series: Option<Series>,
+    /// People watching without a seat.
+    pub spectators: Vec<Spectator>,
     pub chat: Vec<ChatMessage>,
 }
 
 impl State {
-    pub fn new() -> State {
+    pub fn new(config: Config, rules: &dyn Rules) -> State {
+        let board = rules.new_board();
         State {
-            turn: 'X',
+            turn: rules.first_turn(),
             winner: None,
             players: Vec::new(),
-            board: vec![' ', ' ', ' ', ' ', ' ', ' ', ' ', ' ', ' '],
+            config,
+            sub_boards: rules.sub_boards(&board),
+            board,
+            moves: Vec::new(),
+            undo_request: None,
+            rematch_offer: None,
+            draw_offer: None,
+            clock: config.time_control.map(Clock::new),
+            series: config.best_of.map(Series::new),
+            spectators: Vec::new(),
             chat: Vec::new(),
         }
     }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MoveRecord {
+    /// Starts at 1 for the first move of each game.
+    pub number: usize,
+    pub player_id: PlayerID,
+    pub team: char,
+    pub space: usize,
+    /// Milliseconds since the Unix epoch.
+    pub time: u64,
+}
+
+/// Something one player proposed that needs the other to agree.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Offer {
+    pub from: PlayerID,
+    /// Milliseconds since the Unix epoch after which the offer lapses.
+    pub expires: u64,
+}
+
+/// How long a rematch offer stays open.
+const REMATCH_OFFER_MILLIS: u64 = 60_000;
+
+/// How long a seat is held for a player whose connection dropped.
+pub const RECONNECT_GRACE_MILLIS: u64 = 60_000;
+
+/// Most people that may watch one game.
+const MAX_SPECTATORS: usize = 100;
+
+/// Progress of the smaller boards in variants made of several.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SubBoards {
+    /// Result of each small board, or None while it is still being played.
+    pub results: Vec<Option<EndState>>,
+    /// The small board the next move has to be made in, if it is restricted.
+    pub forced: Option<usize>,
+}
+
+/// The rule set, board dimensions and number in a row needed to win, chosen
+/// when the room is created. The classic game is 3x3 with three in a row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Config {
+    pub variant: Variant,
+    pub width: usize,
+    pub height: usize,
+    /// Number of layers, 1 unless the board is 3D.
+    pub depth: usize,
+    pub win_length: usize,
+    /// Number of 3x3 boards in Notakto, always 1 otherwise.
+    pub boards: usize,
+    /// Completing a line loses instead of wins.
+    pub misere: bool,
+    /// Players may ask the server for the best move.
+    pub hints: bool,
+    /// No time limit if None.
+    pub time_control: Option<TimeControl>,
+    pub early_draw: EarlyDraw,
+    /// Number of games in a match, or None to keep playing indefinitely.
+    pub best_of: Option<usize>,
+    /// Spectators may post in the chat.
+    pub spectator_chat: bool,
+    /// Listed in the lobby, where anyone may take an open seat.
+    pub public: bool,
+}
+
+impl Config {
+    pub const MIN_SIZE: usize = 3;
+    pub const MAX_SIZE: usize = 19;
+    pub const MAX_BOARDS: usize = 5;
+    pub const MAX_BEST_OF: usize = 7;
+
+    /// Fills in whatever the room creator left out. Height defaults to the
+    /// width, and the win length to the shorter side (at most five). Variants
+    /// with a fixed geometry ignore the requested size.
+    pub fn new(
+        variant: Variant,
+        width: Option<usize>,
+        height: Option<usize>,
+        win_length: Option<usize>,
+        boards: Option<usize>,
+        misere: bool,
+    ) -> Config {
+        let (width, height, depth, win_length) = match variant {
+            Variant::Classic => {
+                let width = width.unwrap_or(3);
+                let height = height.unwrap_or(width);
+                let win_length = win_length.unwrap_or(width.min(height).min(5));
+                (width, height, 1, win_length)
+            }
+            Variant::Ultimate => (9, 9, 1, 3),
+            Variant::Gravity => {
+                let width = width.unwrap_or(7);
+                let height = height.unwrap_or(6);
+                let win_length = win_length.unwrap_or(width.min(height).min(4));
+                (width, height, 1, win_length)
+            }
+            Variant::Notakto => (3, 3, 1, 3),
+            Variant::Qubic => (4, 4, 4, 4),
+        };
+
+        Config {
+            variant,
+            width,
+            height,
+            depth,
+            win_length,
+            boards: match variant {
+                Variant::Notakto => boards.unwrap_or(1),
+                _ => 1,
+            },
+            // Notakto is already played to avoid lines
+            misere: misere && variant != Variant::Notakto,
+            hints: true,
+            time_control: None,
+            early_draw: EarlyDraw::Never,
+            best_of: None,
+            spectator_chat: true,
+            public: false,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let sizes = Config::MIN_SIZE..=Config::MAX_SIZE;
+        if !sizes.contains(&self.width) || !sizes.contains(&self.height) {
+            return Err(format!(
+                "Board must be between {} and {} cells on each side",
+                Config::MIN_SIZE,
+                Config::MAX_SIZE
+            ));
+        }
+        if self.win_length < Config::MIN_SIZE
+            || self.win_length > self.width.max(self.height).max(self.depth)
+        {
+            return Err("Win length does not fit on the board".to_string());
+        }
+        if self.boards < 1 || self.boards > Config::MAX_BOARDS {
+            return Err(format!("Between 1 and {} boards", Config::MAX_BOARDS));
+        }
+        if let Some(best_of) = self.best_of {
+            if !(3..=Config::MAX_BEST_OF).contains(&best_of) || best_of % 2 == 0 {
+                return Err(format!(
+                    "A series must be an odd number of games from 3 to {}",
+                    Config::MAX_BEST_OF
+                ));
+            }
+        }
+        if let Some(time_control) = &self.time_control {
+            time_control.validate()?;
+        }
+        Ok(())
+    }
+
+    pub fn rules(&self) -> Box<dyn Rules> {
+        let rules = self.variant.rules(self);
+        if self.misere {
+            Box::new(misere::Misere::new(rules))
+        } else {
+            rules
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config::new(Variant::default(), None, None, None, None, false)
+    }
+}
+
+/// A grid of cells stored row by row, and for 3D boards layer by layer, so
+/// that space `i` is at column `i % width`, row `i / width % height` and
+/// layer `i / (width * height)`. Flat boards have a depth of 1.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Board {
+    pub width: usize,
+    pub height: usize,
+    pub depth: usize,
+    pub cells: Vec<char>,
+    pub last_move: Option<usize>,
+}
+
+impl Board {
+    pub fn new(width: usize, height: usize) -> Board {
+        Board::new_3d(width, height, 1)
+    }
+
+    pub fn new_3d(width: usize, height: usize, depth: usize) -> Board {
+        Board {
+            width,
+            height,
+            depth,
+            cells: vec![' '; width * height * depth],
+            last_move: None,
+        }
+    }
+
+    pub fn place(&mut self, space: usize, piece: char) {
+        self.cells[space] = piece;
+        self.last_move = Some(space);
+    }
+
+    pub fn get(&self, space: usize) -> Option<char> {
+        self.cells.get(space).copied()
+    }
+
+    /// Zero-based (x, y) of a space within its layer.
+    pub fn coords(&self, space: usize) -> (usize, usize) {
+        let (x, y, _) = self.coords_3d(space);
+        (x, y)
+    }
+
+    /// Zero-based (x, y, z) of a space.
+    pub fn coords_3d(&self, space: usize) -> (usize, usize, usize) {
+        let layer = self.width * self.height;
+        (
+            space % self.width,
+            space % layer / self.width,
+            space / layer,
+        )
+    }
+
+    /// Space at (x, y, z), or None if it is off the board.
+    pub fn space_at(&self, x: isize, y: isize, z: isize) -> Option<usize> {
+        if x < 0 || y < 0 || z < 0 {
+            return None;
+        }
+        let (x, y, z) = (x as usize, y as usize, z as usize);
+        if x >= self.width || y >= self.height || z >= self.depth {
+            return None;
+        }
+        Some((z * self.height + y) * self.width + x)
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.cells.iter().all(|&c| c != ' ')
+    }
+
+    /// Width by height, and by depth on 3D boards, like "3x3" or "4x4x4".
+    pub fn dimensions(&self) -> String {
+        if self.depth > 1 {
+            format!("{}x{}x{}", self.width, self.height, self.depth)
+        } else {
+            format!("{}x{}", self.width, self.height)
+        }
+    }
+
+    /// Every direction a line can run in, as (dx, dy, dz) steps, counting
+    /// each direction and its opposite once: 4 on a flat board, 13 on a cube.
+    pub fn directions(&self) -> Vec<(isize, isize, isize)> {
+        let mut directions = Vec::new();
+        for dz in -1..=1 {
+            for dy in -1..=1 {
+                for dx in -1..=1 {
+                    let first_nonzero = [dz, dy, dx].into_iter().find(|&d| d != 0);
+                    if first_nonzero != Some(1) {
+                        continue;
+                    }
+                    if dz != 0 && self.depth == 1 {
+                        continue;
+                    }
+                    directions.push((dx, dy, dz));
+                }
+            }
+        }
+        directions
+    }
+
+    /// Every run of `length` spaces in a straight line, in any direction.
+    pub fn lines(&self, length: usize) -> Vec<Vec<usize>> {
+        let mut lines = Vec::new();
+        let directions = self.directions();
+        for space in 0..self.cells.len() {
+            let (x, y, z) = self.coords_3d(space);
+            let (x, y, z) = (x as isize, y as isize, z as isize);
+            for &(dx, dy, dz) in &directions {
+                let line: Vec<usize> = (0..length as isize)
+                    .map_while(|i| self.space_at(x + i * dx, y + i * dy, z + i * dz))
+                    .collect();
+                if line.len() == length {
+                    lines.push(line);
+                }
+            }
+        }
+        lines
+    }
+
+    /// Every run of at least `length` equal pieces in any direction that the
+    /// piece on `space` is part of, as the spaces of each run.
+    pub fn lines_through(&self, space: usize, length: usize) -> Vec<Vec<usize>> {
+        let mut lines = Vec::new();
+        let team = match self.get(space).filter(|&c| c != ' ') {
+            Some(team) => team,
+            None => return lines,
+        };
+        let (x, y, z) = self.coords_3d(space);
+        let (x, y, z) = (x as isize, y as isize, z as isize);
+
+        for (dx, dy, dz) in self.directions() {
+            let mut line = vec![space];
+            for sign in [-1, 1] {
+                let mut step = 1;
+                while let Some(next) = self.space_at(
+                    x + sign * step * dx,
+                    y + sign * step * dy,
+                    z + sign * step * dz,
+                ) {
+                    if self.cells[next] != team {
+                        break;
+                    }
+                    line.push(next);
+                    step += 1;
+                }
+            }
+            if line.len() >= length {
+                line.sort_unstable();
+                lines.push(line);
+            }
+        }
+
+        lines
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub enum EndState {
-    Win(char),
-    Draw,
+    Win {
+        team: char,
+        reason: EndReason,
+        /// Spaces of every line that decided the game, so clients can
+        /// highlight them. Empty unless it ended on a line.
+        #[serde(default)]
+        lines: Vec<Vec<usize>>,
+    },
+    Draw {
+        reason: EndReason,
+    },
+}
+
+impl EndState {
+    pub fn win(team: char, reason: EndReason) -> EndState {
+        EndState::Win {
+            team,
+            reason,
+            lines: Vec::new(),
+        }
+    }
+
+    /// Game decided by completing `lines`, in `team`'s favour.
+    pub fn line(team: char, lines: Vec<Vec<usize>>) -> EndState {
+        EndState::Win {
+            team,
+            reason: EndReason::Line,
+            lines,
+        }
+    }
+
+    pub fn draw(reason: EndReason) -> EndState {
+        EndState::Draw { reason }
+    }
+
+    /// Team that won, or None for a draw.
+    pub fn winner(&self) -> Option<char> {
+        match self {
+            EndState::Win { team, .. } => Some(*team),
+            EndState::Draw { .. } => None,
+        }
+    }
+}
+
+/// Why a game ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum EndReason {
+    /// A line was completed. In misère and Notakto it is the player who
+    /// completed it that loses.
+    Line,
+    BoardFull,
+    Resignation,
+    /// Both players agreed to a draw.
+    Agreement,
+    /// A player ran out of time.
+    Timeout,
+    /// No line could be completed any more.
+    NoLines,
+    /// Neither side could win against perfect play.
+    Forced,
+}
+
+/// When to end a game as a draw before the board fills up.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum EarlyDraw {
+    /// Play on until the board is full.
+    #[default]
+    Never,
+    /// As soon as neither side can complete a line.
+    NoLines,
+    /// As soon as perfect play from both sides would end in a draw. This is
+    /// only worked out once at most ten spaces are left.
+    Forced,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -44,16 +505,72 @@ pub struct Player {
     pub team: char,
     pub name: String,
     pub wins: i32,
+    /// Set for computer players, which the server moves for.
+    #[serde(default)]
+    pub bot: Option<Difficulty>,
+    /// While the player's connection is down, when their seat will be given
+    /// up, in milliseconds since the Unix epoch.
+    #[serde(default)]
+    pub disconnected: Option<u64>,
+    /// Profile the player's games are rated under, if they have one.
+    #[serde(default)]
+    pub profile: Option<ProfileID>,
+    #[serde(default)]
+    pub rating: Option<i32>,
 }
 
 pub type PlayerID = i32;
 
+/// Identifies a player across games and rooms.
+pub type ProfileID = i64;
+
+/// Everything needed to bring a game back after a restart.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Snapshot {
+    pub config: Config,
+    pub events: Vec<Event>,
+    secrets: HashMap<PlayerID, String>,
+    invite: String,
+}
+
+/// A finished game, as kept for ratings and statistics.
+#[derive(Debug, Clone)]
+pub struct GameResult {
+    pub x: Option<ProfileID>,
+    pub o: Option<ProfileID>,
+    /// None for a draw.
+    pub winner: Option<char>,
+    pub variant: Variant,
+    /// Dimensions of the board, as from `Board::dimensions`, which give the
+    /// spaces their meaning.
+    pub board: String,
+    /// Team and space of the first move.
+    pub opening: Option<(char, usize)>,
+    /// Number of moves played.
+    pub moves: usize,
+}
+
+impl GameResult {
+    /// Whether the game counts towards ratings, which takes two different
+    /// players with profiles.
+    pub fn rated(&self) -> bool {
+        matches!((self.x, self.o), (Some(x), Some(o)) if x != o)
+    }
+}
+
 impl Display for Player {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{} ({})", self.name, self.team)
     }
 }
 
+/// Someone watching the game. Shares its ID space with players.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Spectator {
+    pub id: PlayerID,
+    pub name: String,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ChatMessage {
     pub id: usize,
@@ -64,50 +581,360 @@ pub struct ChatMessage {
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub enum ChatMessageSource {
     Player(PlayerID),
+    Spectator(PlayerID),
     System,
 }
 
 impl Game {
-    pub fn new(id: String) -> (Game, watch::Receiver<State>) {
-        let state = State::new();
+    pub fn new(id: String, config: Config) -> (Game, watch::Receiver<State>) {
+        let rules = config.rules();
+        let state = State::new(config, rules.as_ref());
         let (tx, rx) = watch::channel(state.clone());
 
         let game = Game {
-            id: id,
-            state: state,
+            id,
+            state,
             state_changes: tx,
+            rules,
+            secrets: HashMap::new(),
+            invite: new_secret(),
+            results: Vec::new(),
+            events: Vec::new(),
+            now: now_millis(),
+            hints_given: HashMap::new(),
+        };
+
+        (game, rx)
+    }
+
+    /// Rebuilds a game by applying `events` to a new game with `config`.
+    /// Games it finishes are not recorded again.
+    pub fn replay(
+        id: String,
+        config: Config,
+        events: Vec<Event>,
+    ) -> (Game, watch::Receiver<State>) {
+        let (mut game, rx) = Game::new(id, config);
+        for event in events {
+            if let Err(e) = game.push(event) {
+                debug!("Game: Could not replay event: {}", e);
+            }
+        }
+        game.results.clear();
+        game.broadcast_state();
+        (game, rx)
+    }
+
+    /// Brings back a game saved with `snapshot`. Nobody is connected to it
+    /// any more, so players get the usual time to reconnect.
+    pub fn restore(id: String, snapshot: Snapshot) -> (Game, watch::Receiver<State>) {
+        let (mut game, rx) = Game::replay(id, snapshot.config, snapshot.events);
+        game.secrets = snapshot.secrets;
+        game.invite = snapshot.invite;
+        // Not `record`, as the time the server was down should not count
+        let restarted = Event {
+            time: now_millis(),
+            kind: EventKind::Restarted,
         };
+        if let Err(e) = game.push(restarted) {
+            debug!("Game: Could not restart: {}", e);
+        }
+        game.broadcast_state();
+        (game, rx)
+    }
 
-        return (game, rx);
+    /// What `restore` needs to bring the game back, with only the events
+    /// from `since` on, so that a saved game can be brought up to date
+    /// without writing out its whole log again.
+    pub fn snapshot(&self, since: usize) -> Snapshot {
+        Snapshot {
+            config: self.state.config,
+            events: self.events[since.min(self.events.len())..].to_vec(),
+            secrets: self.secrets.clone(),
+            invite: self.invite.clone(),
+        }
+    }
+
+    /// Everything done to the game so far, oldest first.
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    /// Records an event that happened just now, once it has been applied.
+    /// Time that ran out since the last event is recorded first.
+    fn record(&mut self, kind: EventKind) -> Result<(), String> {
+        let now = now_millis();
+        self.pass_time_at(now);
+        self.push(Event { time: now, kind })
+    }
+
+    /// Applies `event` and adds it to the log. Every event checks all it
+    /// needs before changing anything, so one that cannot be applied leaves
+    /// the game as it was.
+    fn push(&mut self, event: Event) -> Result<(), String> {
+        self.apply(&event)?;
+        self.events.push(event);
+        Ok(())
+    }
+
+    fn apply(&mut self, event: &Event) -> Result<(), String> {
+        self.now = event.time;
+        match &event.kind {
+            EventKind::Joined { name, bot } => self.seat_player(name.clone(), *bot).map(|_| ()),
+            EventKind::Left { player_id } => self.unseat_player(*player_id),
+            EventKind::Disconnected { player_id } => self.mark_disconnected(*player_id),
+            EventKind::Reconnected { player_id } => self.mark_reconnected(*player_id),
+            EventKind::SeatExpired { player_id } => self.give_up_seat(*player_id),
+            EventKind::StartedWatching { name } => self.seat_spectator(name.clone()).map(|_| ()),
+            EventKind::StoppedWatching { spectator_id } => self.unseat_spectator(*spectator_id),
+            EventKind::ProfileLinked {
+                player_id,
+                profile,
+                rating,
+            } => self.set_profile(*player_id, *profile, *rating),
+            EventKind::Rated { profile, rating } => {
+                self.set_rating(*profile, *rating);
+                Ok(())
+            }
+            EventKind::Message { player_id, msg } => self.apply_msg(*player_id, msg.clone()),
+            EventKind::SpectatorMessage { spectator_id, msg } => {
+                self.apply_spectator_msg(*spectator_id, msg.clone())
+            }
+            EventKind::TimePassed => {
+                self.expire_offers();
+                self.check_clock();
+                Ok(())
+            }
+            EventKind::Restarted => {
+                self.restart();
+                Ok(())
+            }
+        }
+    }
+
+    /// Records that time ran out for the player to move or for an offer, if
+    /// it has. Returns whether it had.
+    pub fn pass_time(&mut self) -> bool {
+        self.pass_time_at(now_millis())
+    }
+
+    fn pass_time_at(&mut self, now: u64) -> bool {
+        let offer_expired = self
+            .state
+            .rematch_offer
+            .as_ref()
+            .is_some_and(|offer| offer.expires <= now);
+        let flag_fell = self.clock_running()
+            && self.state.clock.as_ref().is_some_and(|clock| {
+                clock.started.is_some() && clock.left_at(self.state.turn, now) == 0
+            });
+        if !offer_expired && !flag_fell {
+            return false;
+        }
+        let event = Event {
+            time: now,
+            kind: EventKind::TimePassed,
+        };
+        self.push(event).is_ok()
     }
 
     pub fn add_player(&mut self, name: String) -> Result<Player, String> {
-        if self.state.players.len() >= 2 {
+        self.record(EventKind::Joined { name, bot: None })?;
+        let player = self.state.players.last().cloned().unwrap();
+        // Secrets are random, so they are made here rather than when the
+        // event is applied, which has to give the same game every time
+        self.secrets.insert(player.id, new_secret());
+        Ok(player)
+    }
+
+    /// Adds a computer player. The server moves for it whenever it is its
+    /// turn, see `bot_turn`.
+    pub fn add_bot(&mut self, difficulty: Difficulty) -> Result<Player, String> {
+        let name = match difficulty {
+            Difficulty::Easy => "Computer (Easy)",
+            Difficulty::Medium => "Computer (Medium)",
+            Difficulty::Hard => "Computer (Hard)",
+        };
+        self.record(EventKind::Joined {
+            name: name.to_string(),
+            bot: Some(difficulty),
+        })?;
+        Ok(self.state.players.last().cloned().unwrap())
+    }
+
+    /// Whether both seats are taken.
+    pub fn is_full(&self) -> bool {
+        self.state.players.len() >= 2
+    }
+
+    fn seat_player(&mut self, name: String, bot: Option<Difficulty>) -> Result<Player, String> {
+        if self.is_full() {
             return Err("Game is full".to_string());
         }
 
+        let id = self.next_id();
         let last_player = self.state.players.last();
-
-        let id = last_player.map(|p| p.id + 1).unwrap_or(0);
         let team = match last_player.map(|p| p.team) {
             Some('X') => 'O',
             _ => 'X',
         };
 
         let player = Player {
-            id: id,
-            team: team,
-            name: name,
+            id,
+            team,
+            name,
             wins: 0,
+            bot,
+            disconnected: None,
+            profile: None,
+            rating: None,
         };
         self.state.players.push(player.clone());
         self.add_chat_message(
             ChatMessageSource::System,
             format!("{} ({}) has joined the game", player.name, player.team),
         );
+        self.check_clock();
         Ok(player)
     }
 
+    /// Code that lets someone join as a player. Anyone with just the game's
+    /// token can only watch.
+    pub fn invite(&self) -> &str {
+        &self.invite
+    }
+
+    /// Secret a player can use to reclaim their seat with `reconnect`.
+    pub fn player_secret(&self, id: PlayerID) -> Option<String> {
+        self.secrets.get(&id).cloned()
+    }
+
+    /// Checks that `secret` is the current secret for the seat of `id`.
+    pub fn authorize(&self, id: PlayerID, secret: &str) -> Result<(), String> {
+        match self.secrets.get(&id) {
+            Some(s) if s == secret => Ok(()),
+            _ => Err("You no longer hold this seat".to_string()),
+        }
+    }
+
+    /// Holds the seat of a player whose connection dropped, for
+    /// `RECONNECT_GRACE_MILLIS`.
+    pub fn disconnect_player(&mut self, id: PlayerID) {
+        let _ = self.record(EventKind::Disconnected { player_id: id });
+    }
+
+    fn mark_disconnected(&mut self, id: PlayerID) -> Result<(), String> {
+        let deadline = self.now + RECONNECT_GRACE_MILLIS;
+        let player = self.get_player_mut(id).ok_or("Invalid player ID")?;
+        player.disconnected = Some(deadline);
+        let text = format!("{} lost their connection", player.name);
+        self.add_chat_message(ChatMessageSource::System, text);
+        // Nobody loses on time while they cannot move
+        self.check_clock();
+        Ok(())
+    }
+
+    /// Gives the seat held for `secret` to a new connection. The secret is
+    /// replaced, so any older connection for the seat is locked out.
+    pub fn reconnect(&mut self, secret: &str) -> Result<Player, String> {
+        let id = self
+            .secrets
+            .iter()
+            .find(|(_, s)| s.as_str() == secret)
+            .map(|(&id, _)| id)
+            .ok_or("No seat for that secret")?;
+        self.record(EventKind::Reconnected { player_id: id })?;
+        self.secrets.insert(id, new_secret());
+
+        let idx = self.get_player_index(id).ok_or("Invalid player ID")?;
+        Ok(self.state.players[idx].clone())
+    }
+
+    fn mark_reconnected(&mut self, id: PlayerID) -> Result<(), String> {
+        let player = self.get_player_mut(id).ok_or("Invalid player ID")?;
+        if player.disconnected.take().is_some() {
+            let text = format!("{} is back", player.name);
+            self.add_chat_message(ChatMessageSource::System, text);
+        }
+        // Their clock goes on from where it stopped if it is their move
+        self.check_clock();
+        Ok(())
+    }
+
+    /// Gives up the seat of a player who did not come back in time. Returns
+    /// whether it did.
+    pub fn expire_seat(&mut self, id: PlayerID) -> bool {
+        let expired = self
+            .record(EventKind::SeatExpired { player_id: id })
+            .is_ok();
+        if expired {
+            self.secrets.remove(&id);
+        }
+        expired
+    }
+
+    fn give_up_seat(&mut self, id: PlayerID) -> Result<(), String> {
+        let expired = self
+            .state
+            .players
+            .iter()
+            .find(|p| p.id == id)
+            .and_then(|p| p.disconnected)
+            .is_some_and(|deadline| deadline <= self.now);
+        if !expired {
+            return Err("Seat is not waiting to be given up".to_string());
+        }
+        self.unseat_player(id)
+    }
+
+    /// Lets someone watch the game and, if the room allows it, chat.
+    pub fn add_spectator(&mut self, name: String) -> Result<Spectator, String> {
+        self.record(EventKind::StartedWatching { name })?;
+        Ok(self.state.spectators.last().cloned().unwrap())
+    }
+
+    fn seat_spectator(&mut self, name: String) -> Result<Spectator, String> {
+        if self.state.spectators.len() >= MAX_SPECTATORS {
+            return Err("Too many spectators".to_string());
+        }
+
+        let spectator = Spectator {
+            id: self.next_id(),
+            name,
+        };
+        self.state.spectators.push(spectator.clone());
+        self.add_chat_message(
+            ChatMessageSource::System,
+            format!("{} is watching", spectator.name),
+        );
+        Ok(spectator)
+    }
+
+    pub fn remove_spectator(&mut self, id: PlayerID) {
+        let _ = self.record(EventKind::StoppedWatching { spectator_id: id });
+    }
+
+    fn unseat_spectator(&mut self, id: PlayerID) -> Result<(), String> {
+        let spectator = self
+            .state
+            .spectators
+            .iter()
+            .find(|s| s.id == id)
+            .ok_or("Invalid spectator ID")?;
+        self.add_chat_message(
+            ChatMessageSource::System,
+            format!("{} stopped watching", spectator.name),
+        );
+        self.state.spectators.retain(|s| s.id != id);
+        Ok(())
+    }
+
+    fn next_id(&self) -> PlayerID {
+        let players = self.state.players.iter().map(|p| p.id);
+        let spectators = self.state.spectators.iter().map(|s| s.id);
+        players.chain(spectators).max().map_or(0, |id| id + 1)
+    }
+
     /// internal trusted function that always succeeds unless the id is bad
     fn update_player_name(&mut self, id: PlayerID, name: String) -> Result<(), String> {
         let player = self.get_player_mut(id).ok_or("Invalid player ID")?;
@@ -115,14 +942,23 @@ impl Game {
         Ok(())
     }
 
+    /// Adds a message typed by a player or spectator.
+    fn chat(&mut self, source: ChatMessageSource, text: &str) -> Result<(), String> {
+        let trimmed = text.trim();
+        if trimmed.is_empty() {
+            return Err("Empty message".to_string());
+        }
+        if trimmed.len() > 500 {
+            return Err("Message too long".to_string());
+        }
+        self.add_chat_message(source, trimmed.to_string());
+        Ok(())
+    }
+
     /// Internal trusted version
     fn add_chat_message(&mut self, source: ChatMessageSource, text: String) {
         let id = self.state.chat.len();
-        self.state.chat.push(ChatMessage {
-            id: id,
-            source: source,
-            text: text,
-        });
+        self.state.chat.push(ChatMessage { id, source, text });
     }
 
     pub fn get_player_index(&self, id: PlayerID) -> Option<usize> {
@@ -137,19 +973,106 @@ impl Game {
         self.state.players.iter_mut().find(|p| p.id == id)
     }
 
-    pub fn remove_player(&mut self, id: PlayerID) {
-        let player = match self.state.players.iter().find(|p| p.id == id) {
-            Some(p) => p,
-            None => return,
+    /// Rates the games of player `id` under `profile` from now on.
+    pub fn link_profile(&mut self, id: PlayerID, profile: ProfileID, rating: i32) {
+        let _ = self.record(EventKind::ProfileLinked {
+            player_id: id,
+            profile,
+            rating,
+        });
+    }
+
+    fn set_profile(&mut self, id: PlayerID, profile: ProfileID, rating: i32) -> Result<(), String> {
+        let player = self.get_player_mut(id).ok_or("Invalid player ID")?;
+        player.profile = Some(profile);
+        player.rating = Some(rating);
+        Ok(())
+    }
+
+    /// Shows the new rating of whoever plays under `profile`.
+    pub fn update_rating(&mut self, profile: ProfileID, rating: i32) {
+        let _ = self.record(EventKind::Rated { profile, rating });
+    }
+
+    fn set_rating(&mut self, profile: ProfileID, rating: i32) {
+        for player in &mut self.state.players {
+            if player.profile == Some(profile) {
+                player.rating = Some(rating);
+            }
+        }
+    }
+
+    /// Hands over the games finished since last time, to be recorded.
+    pub fn take_results(&mut self) -> Vec<GameResult> {
+        std::mem::take(&mut self.results)
+    }
+
+    /// Whether anyone but computer players is left in the game.
+    pub fn has_humans(&self) -> bool {
+        self.state.players.iter().any(|p| p.bot.is_none())
+    }
+
+    /// Computer players take back moves and play again whenever asked. Draw
+    /// offers take some thought, so they are answered by `bot_turn`.
+    fn answer_for_bots(&mut self) {
+        let bot_opponent = |game: &Game, id: PlayerID| {
+            game.state
+                .players
+                .iter()
+                .find(|p| p.id != id && p.bot.is_some())
+                .map(|p| (p.id, p.team))
         };
+
+        let mut answers = Vec::new();
+        if let Some((bot, _)) = self
+            .state
+            .undo_request
+            .and_then(|id| bot_opponent(self, id))
+        {
+            answers.push((bot, FromBrowser::AcceptUndo));
+        }
+        let rematch = self.state.rematch_offer.as_ref().map(|offer| offer.from);
+        if let Some((bot, _)) = rematch.and_then(|id| bot_opponent(self, id)) {
+            answers.push((bot, FromBrowser::AcceptRematch));
+        }
+
+        for (player_id, msg) in answers {
+            if let Err(e) = self.record(EventKind::Message { player_id, msg }) {
+                debug!("Game: Bot {} could not answer: {}", player_id, e);
+            }
+        }
+    }
+
+    pub fn remove_player(&mut self, id: PlayerID) {
+        if self.record(EventKind::Left { player_id: id }).is_ok() {
+            self.secrets.remove(&id);
+        }
+    }
+
+    fn unseat_player(&mut self, id: PlayerID) -> Result<(), String> {
+        let player = self
+            .state
+            .players
+            .iter()
+            .find(|p| p.id == id)
+            .ok_or("Invalid player ID")?;
         self.add_chat_message(
             ChatMessageSource::System,
             format!("{} has left the game", player.name),
         );
         self.state.players.retain(|p| p.id != id);
+        // A series is between two particular players
+        self.state.series = self.state.config.best_of.map(Series::new);
+        self.state.undo_request = None;
+        self.state.rematch_offer = None;
+        self.state.draw_offer = None;
+        // Stop the clock until the seat is taken again, charging the player
+        // to move for their time so far
+        self.check_clock();
+        Ok(())
     }
 
-    pub fn take_turn(&mut self, player_id: PlayerID, space: usize) -> Result<(), String> {
+    fn take_turn(&mut self, player_id: PlayerID, target: Target) -> Result<(), String> {
         if self.state.players.len() < 2 {
             return Err("Not enough players".to_string());
         }
@@ -168,80 +1091,491 @@ impl Game {
             return Err("Not your turn".to_string());
         }
 
-        if self.state.board[space] != ' ' {
-            return Err("Invalid move".to_string());
+        let out_of_time = self
+            .state
+            .clock
+            .as_ref()
+            .is_some_and(|clock| clock.left_at(team, self.now) == 0);
+        if out_of_time {
+            return Err("Out of time".to_string());
         }
 
-        self.state.board[space] = team;
-        self.state.turn = if self.state.turn == 'X' { 'O' } else { 'X' };
+        let space = self.rules.resolve(&self.state.board, &target)?;
+        self.rules.check_move(&self.state.board, team, space)?;
+        // Charge the player for the time they took
+        self.check_clock();
+        self.rules.play(&mut self.state.board, team, space);
+        if let (Some(clock), Some(time_control)) =
+            (&mut self.state.clock, self.state.config.time_control)
+        {
+            clock.moved(team, time_control);
+        }
+        self.state.turn = self.rules.next_turn(&self.state.board, team);
+        // Start the next player's clock, unless they are away
+        self.check_clock();
+        self.state.sub_boards = self.rules.sub_boards(&self.state.board);
+        self.state.moves.push(MoveRecord {
+            number: self.state.moves.len() + 1,
+            player_id,
+            team,
+            space,
+            time: self.now,
+        });
+        self.state.undo_request = None;
+        self.state.draw_offer = None;
 
+        let piece = self.state.board.cells[space];
+        let description = self.rules.describe_space(&self.state.board, space);
         self.add_chat_message(
             ChatMessageSource::Player(player_id),
-            format!("Played {} at ({}, {}).", team, space % 3 + 1, space / 3 + 1),
+            format!("Played {} at {}.", piece, description),
         );
 
-        if let Some(winning_team) = self.check_for_win() {
-            self.state.winner = Some(EndState::Win(winning_team));
-            let winner_idx = self.get_player_index_by_team(winning_team).unwrap();
-            self.state.players[winner_idx].wins += 1;
+        if let Some(end) = self
+            .rules
+            .end_state(&self.state.board, team, space)
+            .or_else(|| self.early_draw())
+        {
+            self.finish(end);
+        }
+
+        Ok(())
+    }
+
+    /// A draw if the room asked for dead positions to end early and this one
+    /// is.
+    fn early_draw(&self) -> Option<EndState> {
+        let board = &self.state.board;
+        if self.state.config.early_draw == EarlyDraw::Never {
+            return None;
+        }
+        if !self.rules.line_possible(board) {
+            return Some(EndState::draw(EndReason::NoLines));
+        }
+        if self.state.config.early_draw == EarlyDraw::Forced
+            && engine::solve(self.rules.as_ref(), board, self.state.turn) == Some(Outcome::Draw)
+        {
+            return Some(EndState::draw(EndReason::Forced));
+        }
+        None
+    }
+
+    /// Ends the game, crediting the winner if there is one.
+    fn finish(&mut self, end: EndState) {
+        self.state.undo_request = None;
+        self.state.draw_offer = None;
+        if let Some(clock) = &mut self.state.clock {
+            clock.started = None;
+        }
+        match end.winner() {
+            Some(team) => {
+                let winner_idx = self.get_player_index_by_team(team).unwrap();
+                self.state.players[winner_idx].wins += 1;
+                self.add_chat_message(
+                    ChatMessageSource::System,
+                    format!("{} wins!", self.state.players[winner_idx]),
+                );
+            }
+            None => {
+                self.add_chat_message(ChatMessageSource::System, "It's a draw!".to_string());
+            }
+        }
+        self.record_series(end.winner());
+        self.record_result(end.winner());
+        self.state.winner = Some(end);
+    }
+
+    /// Profile of the player on `team`, if they have one.
+    fn profile_of(&self, team: char) -> Option<ProfileID> {
+        self.get_player_index_by_team(team)
+            .and_then(|idx| self.state.players[idx].profile)
+    }
+
+    /// Whether the game counts towards ratings, see `GameResult::rated`.
+    pub fn is_rated(&self) -> bool {
+        matches!(
+            (self.profile_of('X'), self.profile_of('O')),
+            (Some(x), Some(o)) if x != o
+        )
+    }
+
+    /// Queues the game to be recorded if either player has a profile.
+    fn record_result(&mut self, winner: Option<char>) {
+        let (x, o) = (self.profile_of('X'), self.profile_of('O'));
+        if x.is_none() && o.is_none() {
+            return;
+        }
+        self.results.push(GameResult {
+            x,
+            o,
+            winner,
+            variant: self.state.config.variant,
+            board: self.state.board.dimensions(),
+            opening: self.state.moves.first().map(|m| (m.team, m.space)),
+            moves: self.state.moves.len(),
+        });
+    }
+
+    /// Adds the game `winner` just won to the series, if there is one, and
+    /// announces the result once it is decided.
+    fn record_series(&mut self, winner: Option<char>) {
+        let player_id = |team: char| {
+            self.get_player_index_by_team(team)
+                .map(|idx| self.state.players[idx].id)
+        };
+        let game = match player_id(self.rules.first_turn()) {
+            Some(started) => SeriesGame {
+                started,
+                winner: winner.and_then(player_id),
+            },
+            None => return,
+        };
+        let players = [self.state.players[0].id, self.state.players[1].id];
+        let series = match &mut self.state.series {
+            Some(series) if !series.finished => series,
+            _ => return,
+        };
+        series.record(game, players);
+        if !series.finished {
+            return;
+        }
+
+        let series = series.clone();
+        let [a, b] = players.map(|p| series.wins(p));
+        let text = match series.winner.and_then(|id| self.get_player_index(id)) {
+            Some(idx) => format!(
+                "{} wins the series {}-{}!",
+                self.state.players[idx].name,
+                a.max(b),
+                a.min(b)
+            ),
+            None => format!("The series ends level at {}-{}.", a, b),
+        };
+        self.add_chat_message(ChatMessageSource::System, text);
+    }
+
+    /// Whether the clock of the player to move should run: the game is on
+    /// and they are connected to make their move.
+    fn clock_running(&self) -> bool {
+        self.state.players.len() >= 2
+            && self.state.winner.is_none()
+            && self
+                .state
+                .players
+                .iter()
+                .any(|p| p.team == self.state.turn && p.disconnected.is_none())
+    }
+
+    /// Charges the player to move for the time since the clock last ran,
+    /// starting or stopping the clock as players come and go. Returns whether
+    /// that ended the game because their time ran out.
+    fn check_clock(&mut self) -> bool {
+        let running = self.clock_running();
+        let turn = self.state.turn;
+        let clock = match &mut self.state.clock {
+            Some(clock) => clock,
+            None => return false,
+        };
+        if !running {
+            // Time used so far still counts when the clock starts again
+            if clock.started.is_some() {
+                clock.tick(turn, self.now);
+            }
+            clock.started = None;
+            return false;
+        }
+        if clock.tick(turn, self.now) > 0 {
+            return false;
+        }
+
+        if let Some(idx) = self.get_player_index_by_team(turn) {
             self.add_chat_message(
                 ChatMessageSource::System,
-                format!("{} wins!", self.state.players[winner_idx]),
+                format!("{} ran out of time.", self.state.players[idx]),
             );
-        } else if self.check_for_draw() {
-            self.state.winner = Some(EndState::Draw);
-            self.add_chat_message(ChatMessageSource::System, "It's a draw!".to_string());
         }
+        self.finish(EndState::win(other_team(turn), EndReason::Timeout));
+        true
+    }
+
+    /// How long until time next runs out, for the player to move or for a
+    /// rematch offer. None if nothing is waiting on the time.
+    pub fn next_timeout(&self) -> Option<Duration> {
+        let now = now_millis();
+        let flag_fall = self
+            .state
+            .clock
+            .as_ref()
+            .filter(|clock| clock.started.is_some() && self.clock_running())
+            .map(|clock| clock.left_at(self.state.turn, now));
+        let offer_expiry = self
+            .state
+            .rematch_offer
+            .as_ref()
+            .map(|offer| offer.expires.saturating_sub(now));
+        let left = match (flag_fall, offer_expiry) {
+            (Some(a), Some(b)) => a.min(b),
+            (a, b) => a.or(b)?,
+        };
+        Some(Duration::from_millis(left))
+    }
+
+    /// Concedes the game to the opponent.
+    fn resign(&mut self, player_id: PlayerID) -> Result<(), String> {
+        if self.state.players.len() < 2 {
+            return Err("Not enough players".to_string());
+        }
+        if self.state.winner.is_some() {
+            return Err("Game is over".to_string());
+        }
+        let player_idx = self
+            .get_player_index(player_id)
+            .ok_or("Invalid player ID")?;
+        let team = self.state.players[player_idx].team;
 
+        self.add_chat_message(
+            ChatMessageSource::Player(player_id),
+            "I resign.".to_string(),
+        );
+        self.finish(EndState::win(other_team(team), EndReason::Resignation));
         Ok(())
     }
 
-    pub fn broadcast_state(&self) {
-        self.state_changes.send_replace(self.state.clone());
+    /// Offers the opponent a draw, or accepts theirs if they already offered
+    /// one.
+    fn offer_draw(&mut self, player_id: PlayerID) -> Result<(), String> {
+        match self.state.draw_offer {
+            Some(from) if from != player_id => return self.answer_draw(player_id, true),
+            Some(_) => return Err("Draw already offered".to_string()),
+            None => {}
+        }
+        if self.state.players.len() < 2 {
+            return Err("Not enough players".to_string());
+        }
+        if self.state.winner.is_some() {
+            return Err("Game is over".to_string());
+        }
+
+        self.state.draw_offer = Some(player_id);
+        self.add_chat_message(
+            ChatMessageSource::Player(player_id),
+            "Shall we call it a draw?".to_string(),
+        );
+        Ok(())
     }
 
-    fn check_for_win(&self) -> Option<char> {
-        let winning_combos = [
-            [0, 1, 2],
-            [3, 4, 5],
-            [6, 7, 8],
-            [0, 3, 6],
-            [1, 4, 7],
-            [2, 5, 8],
-            [0, 4, 8],
-            [2, 4, 6],
-        ];
-
-        for combo in winning_combos.iter() {
-            let mut winner = self.state.board[combo[0]];
-            if winner == ' ' {
-                continue;
-            }
+    fn answer_draw(&mut self, player_id: PlayerID, accept: bool) -> Result<(), String> {
+        match self.state.draw_offer {
+            Some(from) if from != player_id => {}
+            _ => return Err("No draw offer to answer".to_string()),
+        }
+        self.state.draw_offer = None;
 
-            for i in 1..3 {
-                if self.state.board[combo[i]] != winner {
-                    winner = ' ';
-                    break;
-                }
-            }
+        if !accept {
+            self.add_chat_message(ChatMessageSource::Player(player_id), "No.".to_string());
+            return Ok(());
+        }
+
+        self.add_chat_message(ChatMessageSource::Player(player_id), "Agreed.".to_string());
+        self.finish(EndState::draw(EndReason::Agreement));
+        Ok(())
+    }
+
+    /// Asks the opponent to let `player_id` take back their last move.
+    fn request_undo(&mut self, player_id: PlayerID) -> Result<(), String> {
+        if self.state.winner.is_some() {
+            return Err("Game is over".to_string());
+        }
+        if !self.state.moves.iter().any(|m| m.player_id == player_id) {
+            return Err("Nothing to undo".to_string());
+        }
+        if self.state.undo_request.is_some() {
+            return Err("An undo is already waiting for an answer".to_string());
+        }
+
+        self.state.undo_request = Some(player_id);
+        self.add_chat_message(
+            ChatMessageSource::Player(player_id),
+            "May I take back my last move?".to_string(),
+        );
+        Ok(())
+    }
 
-            if winner != ' ' {
-                return Some(winner);
+    /// The opponent's answer to an undo request. Accepting takes back the
+    /// requester's last move and anything played after it, so it is their
+    /// turn again.
+    fn answer_undo(&mut self, player_id: PlayerID, accept: bool) -> Result<(), String> {
+        let requester = match self.state.undo_request {
+            Some(requester) if requester != player_id => requester,
+            _ => return Err("No undo request to answer".to_string()),
+        };
+        let last_move = self
+            .state
+            .moves
+            .iter()
+            .rposition(|m| m.player_id == requester);
+        let from = match (accept, last_move) {
+            (false, _) => {
+                self.state.undo_request = None;
+                self.add_chat_message(ChatMessageSource::Player(player_id), "No.".to_string());
+                return Ok(());
             }
+            (true, None) => return Err("Nothing to undo".to_string()),
+            (true, Some(from)) => from,
+        };
+        self.state.undo_request = None;
+
+        // Charge the player to move for their time before the turn changes
+        self.check_clock();
+        let undone = self.state.moves.len() - from;
+        self.state.moves.truncate(from);
+        self.replay_moves();
+        if let (Some(clock), Some(time_control)) =
+            (&mut self.state.clock, self.state.config.time_control)
+        {
+            clock.took_back(time_control);
         }
+        // Start the clock of whoever moves now, unless they are away
+        self.check_clock();
 
-        None
+        self.add_chat_message(ChatMessageSource::Player(player_id), "OK.".to_string());
+        self.add_chat_message(
+            ChatMessageSource::System,
+            format!(
+                "Took back {} move{}.",
+                undone,
+                if undone == 1 { "" } else { "s" }
+            ),
+        );
+        Ok(())
+    }
+
+    /// Rebuilds the board and turn from the move list.
+    fn replay_moves(&mut self) {
+        let mut board = self.rules.new_board();
+        let mut turn = self.rules.first_turn();
+        for m in &self.state.moves {
+            self.rules.play(&mut board, m.team, m.space);
+            turn = self.rules.next_turn(&board, m.team);
+        }
+        self.state.sub_boards = self.rules.sub_boards(&board);
+        self.state.board = board;
+        self.state.turn = turn;
     }
 
-    fn check_for_draw(&self) -> bool {
-        self.state.board.iter().all(|&c| c != ' ')
+    pub fn broadcast_state(&self) {
+        self.state_changes.send_replace(self.state.clone());
     }
 
     fn reset(&mut self) {
-        self.state.board.iter_mut().for_each(|c| *c = ' ');
-        self.state.turn = 'X';
+        self.state.board = self.rules.new_board();
+        self.state.sub_boards = self.rules.sub_boards(&self.state.board);
+        self.state.turn = self.rules.first_turn();
         self.state.winner = None;
+        self.state.moves.clear();
+        self.state.undo_request = None;
+        self.state.rematch_offer = None;
+        self.state.draw_offer = None;
+        self.state.clock = self.state.config.time_control.map(Clock::new);
+    }
+
+    /// Proposes starting a new game with sides swapped, or accepts the
+    /// opponent's proposal if they already made one.
+    fn offer_rematch(&mut self, player_id: PlayerID) -> Result<(), String> {
+        match &self.state.rematch_offer {
+            Some(offer) if offer.from != player_id => return self.answer_rematch(player_id, true),
+            Some(_) => return Err("Rematch already offered".to_string()),
+            None => {}
+        }
+        if self.state.players.len() < 2 {
+            return Err("Not enough players".to_string());
+        }
+
+        self.state.rematch_offer = Some(Offer {
+            from: player_id,
+            expires: self.now + REMATCH_OFFER_MILLIS,
+        });
+        let text = if self.state.winner.is_some() {
+            "Rematch?"
+        } else {
+            "Shall we start over?"
+        };
+        self.add_chat_message(ChatMessageSource::Player(player_id), text.to_string());
+        Ok(())
+    }
+
+    fn answer_rematch(&mut self, player_id: PlayerID, accept: bool) -> Result<(), String> {
+        match &self.state.rematch_offer {
+            Some(offer) if offer.from != player_id => {}
+            _ => return Err("No rematch offer to answer".to_string()),
+        }
+        self.state.rematch_offer = None;
+
+        if !accept {
+            self.add_chat_message(
+                ChatMessageSource::Player(player_id),
+                "No rematch.".to_string(),
+            );
+            return Ok(());
+        }
+
+        self.add_chat_message(ChatMessageSource::Player(player_id), "Rematch!".to_string());
+        self.add_chat_message(
+            ChatMessageSource::System,
+            "Players have swapped sides.".to_string(),
+        );
+        let decided = self.state.series.as_ref().is_some_and(|s| s.finished);
+        if decided {
+            self.state.series = self.state.config.best_of.map(Series::new);
+            self.add_chat_message(
+                ChatMessageSource::System,
+                "A new series begins.".to_string(),
+            );
+        }
+        self.reset();
+        self.swap_teams();
+        self.check_clock();
+        Ok(())
+    }
+
+    /// Drops offers nobody answered in time.
+    fn expire_offers(&mut self) {
+        let expired = self
+            .state
+            .rematch_offer
+            .as_ref()
+            .is_some_and(|offer| offer.expires <= self.now);
+        if expired {
+            self.state.rematch_offer = None;
+            self.add_chat_message(
+                ChatMessageSource::System,
+                "The rematch offer expired.".to_string(),
+            );
+        }
     }
+
+    /// Everyone lost their connection when the server went down. Players get
+    /// the usual time to come back, and nobody's clock ran in the meantime.
+    /// The clock of the player to move stays stopped until they are back.
+    fn restart(&mut self) {
+        let now = self.now;
+        self.state.spectators.clear();
+        for player in &mut self.state.players {
+            if player.bot.is_none() {
+                player.disconnected = Some(now + RECONNECT_GRACE_MILLIS);
+            }
+        }
+        if let Some(clock) = &mut self.state.clock {
+            clock.started = None;
+        }
+        self.add_chat_message(
+            ChatMessageSource::System,
+            "The server restarted".to_string(),
+        );
+        // A computer player to move has nobody to wait for
+        self.check_clock();
+    }
+
     fn swap_teams(&mut self) {
         self.state.players.iter_mut().for_each(|p| {
             if p.team == 'X' {
@@ -252,54 +1586,167 @@ impl Game {
         });
     }
 
-    pub fn handle_msg(&mut self, player_id: PlayerID, msg: FromBrowser) -> Result<bool, String> {
+    pub fn handle_msg(
+        &mut self,
+        player_id: PlayerID,
+        msg: FromBrowser,
+    ) -> Result<Response, String> {
         debug!("Game: Handle Msg: {:?}", msg);
+        if self.pass_time() {
+            // Whatever the player wanted, the game ended before it arrived
+            self.broadcast_state();
+        }
+        if let FromBrowser::RequestHint = msg {
+            return Ok(Response::Hint(self.hint(player_id)?));
+        }
+        self.record(EventKind::Message { player_id, msg })?;
+        self.answer_for_bots();
+        Ok(Response::Changed)
+    }
+
+    fn apply_msg(&mut self, player_id: PlayerID, msg: FromBrowser) -> Result<(), String> {
         match msg {
             FromBrowser::ChatMsg { text } => {
-                let trimmed = text.trim();
-                if trimmed.len() == 0 {
-                    return Err("Empty message".to_string());
-                }
-                if trimmed.len() > 500 {
-                    return Err("Message too long".to_string());
-                }
-                self.add_chat_message(ChatMessageSource::Player(player_id), trimmed.to_string());
+                self.chat(ChatMessageSource::Player(player_id), &text)?
             }
             FromBrowser::ChangeName { new_name } => {
-                let mut trimmed = new_name.trim();
-                if trimmed.len() == 0 {
-                    trimmed = "Unnamed Player";
-                } else if trimmed.len() > 32 {
-                    trimmed = &trimmed[..32];
-                }
-                self.update_player_name(player_id, trimmed.to_string())
-                    .unwrap();
+                let trimmed = new_name.trim();
+                let name = if trimmed.is_empty() {
+                    "Unnamed Player".to_string()
+                } else {
+                    trimmed.chars().take(32).collect()
+                };
+                self.update_player_name(player_id, name)?;
                 self.add_chat_message(
                     ChatMessageSource::Player(player_id),
                     format!("Now my name is \"{}\"!", new_name),
                 );
             }
-            FromBrowser::Move { space } => self.take_turn(player_id, space)?,
-            FromBrowser::Rematch => {
-                self.add_chat_message(ChatMessageSource::Player(player_id), "Rematch!".to_string());
-                self.add_chat_message(
-                    ChatMessageSource::System,
-                    "Players have swapped sides.".to_string(),
-                );
-                self.reset();
-                self.swap_teams();
+            FromBrowser::Move(target) => self.take_turn(player_id, target)?,
+            FromBrowser::RequestUndo => self.request_undo(player_id)?,
+            FromBrowser::AcceptUndo => self.answer_undo(player_id, true)?,
+            FromBrowser::DeclineUndo => self.answer_undo(player_id, false)?,
+            FromBrowser::RequestHint => return Err("Hints do not change the game".to_string()),
+            FromBrowser::Rematch => self.offer_rematch(player_id)?,
+            FromBrowser::AcceptRematch => self.answer_rematch(player_id, true)?,
+            FromBrowser::DeclineRematch => self.answer_rematch(player_id, false)?,
+            FromBrowser::Resign => self.resign(player_id)?,
+            FromBrowser::OfferDraw => self.offer_draw(player_id)?,
+            FromBrowser::AcceptDraw => self.answer_draw(player_id, true)?,
+            FromBrowser::DeclineDraw => self.answer_draw(player_id, false)?,
+        }
+        Ok(())
+    }
+
+    /// Spectators can only chat, and only if the room allows it.
+    pub fn handle_spectator_msg(
+        &mut self,
+        spectator_id: PlayerID,
+        msg: FromBrowser,
+    ) -> Result<Response, String> {
+        debug!("Game: Handle Spectator Msg: {:?}", msg);
+        self.record(EventKind::SpectatorMessage { spectator_id, msg })?;
+        Ok(Response::Changed)
+    }
+
+    fn apply_spectator_msg(
+        &mut self,
+        spectator_id: PlayerID,
+        msg: FromBrowser,
+    ) -> Result<(), String> {
+        match msg {
+            FromBrowser::ChatMsg { text } if self.state.config.spectator_chat => {
+                self.chat(ChatMessageSource::Spectator(spectator_id), &text)
             }
+            FromBrowser::ChatMsg { .. } => Err("Spectators may not chat in this game".to_string()),
+            _ => Err("Spectators can only chat".to_string()),
         }
-        Ok(true)
     }
 }
 
-#[derive(Debug, Clone, Deserialize)]
+pub fn new_secret() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect()
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Seconds since the Unix epoch, as the database keeps times.
+pub fn now_secs() -> u64 {
+    now_millis() / 1000
+}
+
+/// What to do after a message from a player was handled.
+#[derive(Debug)]
+pub enum Response {
+    /// The state changed and everyone should get the new one.
+    Changed,
+    /// The sender asked for a hint, to be worked out without holding the
+    /// game and sent only to them. The state is unchanged.
+    Hint(HintRequest),
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub enum FromBrowser {
-    ChatMsg { text: String },
-    ChangeName { new_name: String },
-    Move { space: usize },
+    ChatMsg {
+        text: String,
+    },
+    ChangeName {
+        new_name: String,
+    },
+    Move(Target),
+    /// Offers a rematch, or accepts the opponent's offer.
     Rematch,
+    AcceptRematch,
+    DeclineRematch,
+    RequestHint,
+    RequestUndo,
+    AcceptUndo,
+    DeclineUndo,
+    Resign,
+    /// Offers a draw, or accepts the opponent's offer.
+    OfferDraw,
+    AcceptDraw,
+    DeclineDraw,
+}
+
+/// Where a player asked to move. Every variant accepts a `space` on the whole
+/// board; some also accept coordinates of their own.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Target {
+    #[serde(default)]
+    pub space: Option<usize>,
+    /// Small board (0 to 8) in Ultimate, with `space` as the cell within it.
+    #[serde(default)]
+    pub sub_board: Option<usize>,
+    /// Column to drop a piece into in Gravity.
+    #[serde(default)]
+    pub column: Option<usize>,
+    /// Zero-based coordinates, as an alternative to `space`. `z` is the
+    /// layer on 3D boards.
+    #[serde(default)]
+    pub x: Option<usize>,
+    #[serde(default)]
+    pub y: Option<usize>,
+    #[serde(default)]
+    pub z: Option<usize>,
+}
+
+impl Target {
+    pub fn space(space: usize) -> Target {
+        Target {
+            space: Some(space),
+            ..Default::default()
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -307,8 +1754,403 @@ pub enum ToBrowser {
     JoinedGame {
         token: String,
         player_id: PlayerID,
+        /// Pass as `secret` when connecting again to take the seat back.
+        secret: String,
+        /// Pass as `invite` along with the token to join as a player.
+        invite: String,
+        /// Pass as `profile` when joining later games to keep your rating.
+        profile: Option<String>,
+        state: State,
+    },
+    /// Sent instead of `JoinedGame` when both seats were taken.
+    Spectating {
+        token: String,
+        spectator_id: PlayerID,
         state: State,
     },
     GameState(State),
+    /// Private answer to `RequestHint`.
+    Hint(Analysis),
     Error(String),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn play(game: &mut Game, id: PlayerID, msg: FromBrowser) {
+        game.handle_msg(id, msg).unwrap();
+    }
+
+    fn move_to(space: usize) -> FromBrowser {
+        FromBrowser::Move(Target::space(space))
+    }
+
+    /// Records `kind` as if it happened at `time`.
+    fn record_at(game: &mut Game, time: u64, kind: EventKind) {
+        game.pass_time_at(time);
+        game.push(Event { time, kind }).unwrap();
+    }
+
+    /// Handles `msg` as if it arrived at `time`.
+    fn play_at(game: &mut Game, time: u64, id: PlayerID, msg: FromBrowser) {
+        record_at(game, time, EventKind::Message { player_id: id, msg });
+    }
+
+    /// A game with `config` and both seats taken, X first.
+    fn two_players(config: Config) -> (Game, PlayerID, PlayerID) {
+        let (mut game, _) = Game::new("test".to_string(), config);
+        let x = game.add_player("A".to_string()).unwrap().id;
+        let o = game.add_player("B".to_string()).unwrap().id;
+        (game, x, o)
+    }
+
+    #[test]
+    fn boards_have_every_line() {
+        assert_eq!(Board::new(3, 3).lines(3).len(), 8);
+        // Rows, columns and both diagonals of 4 on a 4x5 board
+        assert_eq!(Board::new(4, 5).lines(4).len(), 5 + 8 + 2 + 2);
+    }
+
+    #[test]
+    fn qubic_has_76_lines() {
+        let config = Config::new(Variant::Qubic, None, None, None, None, false);
+        let rules = config.rules();
+        let mut board = rules.new_board();
+        assert_eq!(board.dimensions(), "4x4x4");
+        assert_eq!(board.lines(4).len(), 76);
+
+        // Corner to corner through every layer
+        for space in [0, 21, 42, 63] {
+            board.place(space, 'X');
+        }
+        let end = rules.end_state(&board, 'X', 63);
+        assert_eq!(end, Some(EndState::line('X', vec![vec![0, 21, 42, 63]])));
+    }
+
+    #[test]
+    fn lines_through_the_last_move() {
+        let mut board = Board::new(3, 3);
+        for space in [0, 4, 8, 2, 6] {
+            board.place(space, 'X');
+        }
+        let mut lines = board.lines_through(4, 3);
+        lines.iter_mut().for_each(|line| line.sort());
+        lines.sort();
+        assert_eq!(lines, vec![vec![0, 4, 8], vec![2, 4, 6]]);
+        assert!(board.lines_through(1, 3).is_empty());
+    }
+
+    #[test]
+    fn replaying_events_gives_the_live_state() {
+        let config = Config {
+            hints: true,
+            best_of: Some(3),
+            time_control: Some(TimeControl::Total {
+                seconds: 60,
+                increment: 1,
+            }),
+            ..Config::default()
+        };
+        let (mut game, _rx) = Game::new("test".to_string(), config);
+        let x = game.add_player("A".to_string()).unwrap().id;
+        let o = game.add_player("B".to_string()).unwrap().id;
+        game.link_profile(x, 1, 1200);
+        game.link_profile(o, 2, 1250);
+        let spectator = game.add_spectator("C".to_string()).unwrap().id;
+
+        play(&mut game, x, move_to(4));
+        play(&mut game, o, move_to(0));
+        play(&mut game, o, FromBrowser::RequestUndo);
+        play(&mut game, x, FromBrowser::AcceptUndo);
+        play(&mut game, o, move_to(8));
+        play(&mut game, x, FromBrowser::ChatMsg { text: "hi".into() });
+        play(&mut game, x, FromBrowser::OfferDraw);
+        play(&mut game, o, FromBrowser::DeclineDraw);
+        play(
+            &mut game,
+            o,
+            FromBrowser::ChangeName {
+                new_name: "D".into(),
+            },
+        );
+        play(&mut game, o, FromBrowser::Resign);
+        game.update_rating(1, 1216);
+        play(&mut game, x, FromBrowser::Rematch);
+        play(&mut game, o, FromBrowser::AcceptRematch);
+        game.disconnect_player(x);
+        let secret = game.player_secret(x).unwrap();
+        game.reconnect(&secret).unwrap();
+        game.remove_spectator(spectator);
+        play(&mut game, o, move_to(0));
+        assert!(game.handle_msg(o, move_to(1)).is_err());
+
+        let (replayed, _rx) = Game::replay("test".to_string(), config, game.events().to_vec());
+        assert_eq!(
+            serde_json::to_value(&replayed.state).unwrap(),
+            serde_json::to_value(&game.state).unwrap()
+        );
+        assert_eq!(game.state.series.as_ref().unwrap().games.len(), 1);
+    }
+
+    #[test]
+    fn unanswered_rematch_offers_expire() {
+        let (mut game, x, o) = two_players(Config::default());
+        play(&mut game, x, FromBrowser::Rematch);
+        let expires = game.state.rematch_offer.as_ref().unwrap().expires;
+        assert!(game.next_timeout().is_some());
+
+        assert!(!game.pass_time_at(expires - 1));
+        assert!(game.pass_time_at(expires));
+        assert!(game.state.rematch_offer.is_none());
+        assert_eq!(game.next_timeout(), None);
+        assert!(game.handle_msg(o, FromBrowser::AcceptRematch).is_err());
+    }
+
+    #[test]
+    fn resigning_credits_the_opponent() {
+        let (mut game, x, o) = two_players(Config::default());
+        play(&mut game, x, move_to(4));
+        play(&mut game, x, FromBrowser::Resign);
+        assert_eq!(
+            game.state.winner,
+            Some(EndState::win('O', EndReason::Resignation))
+        );
+        let wins: Vec<i32> = game.state.players.iter().map(|p| p.wins).collect();
+        assert_eq!(wins, vec![0, 1]);
+        assert!(game.handle_msg(o, FromBrowser::Resign).is_err());
+    }
+
+    #[test]
+    fn agreed_draws_credit_nobody() {
+        let (mut game, x, o) = two_players(Config::default());
+        play(&mut game, o, FromBrowser::OfferDraw);
+        assert!(game.handle_msg(o, FromBrowser::AcceptDraw).is_err());
+        play(&mut game, x, FromBrowser::DeclineDraw);
+        assert_eq!(game.state.winner, None);
+
+        // Offering back accepts an offer already made
+        play(&mut game, x, FromBrowser::OfferDraw);
+        play(&mut game, o, FromBrowser::OfferDraw);
+        assert_eq!(
+            game.state.winner,
+            Some(EndState::draw(EndReason::Agreement))
+        );
+        assert!(game.state.players.iter().all(|p| p.wins == 0));
+    }
+
+    #[test]
+    fn taking_back_a_move_gives_back_the_time_for_it() {
+        let config = Config {
+            time_control: Some(TimeControl::PerMove { seconds: 30 }),
+            ..Config::default()
+        };
+        let (mut game, x, o) = two_players(config);
+        let start = game.now;
+        play_at(&mut game, start + 1_000, x, move_to(4));
+        play_at(&mut game, start + 2_000, x, FromBrowser::RequestUndo);
+        play_at(&mut game, start + 27_000, o, FromBrowser::AcceptUndo);
+
+        assert_eq!(game.state.turn, 'X');
+        let clock = game.state.clock.as_ref().unwrap();
+        assert_eq!(clock.left_at('X', start + 27_000), 30_000);
+        assert_eq!(clock.remaining('O'), 30_000);
+    }
+
+    #[test]
+    fn spectators_can_only_chat() {
+        let (mut game, x, _) = two_players(Config::default());
+        let spectator = game.add_spectator("C".to_string()).unwrap().id;
+        assert_ne!(spectator, x);
+
+        for msg in [move_to(0), FromBrowser::Rematch, FromBrowser::Resign] {
+            assert!(game.handle_spectator_msg(spectator, msg).is_err());
+        }
+        // Nor can they pass for a player
+        assert!(game.handle_msg(spectator, move_to(0)).is_err());
+        assert!(game.state.moves.is_empty());
+
+        let chat = FromBrowser::ChatMsg { text: "hi".into() };
+        assert!(game.handle_spectator_msg(spectator, chat.clone()).is_ok());
+        game.state.config.spectator_chat = false;
+        assert!(game.handle_spectator_msg(spectator, chat).is_err());
+    }
+
+    #[test]
+    fn dropped_players_keep_their_seat_for_a_while() {
+        let (mut game, x, _) = two_players(Config::default());
+        game.disconnect_player(x);
+        let deadline = game.state.players[0].disconnected.unwrap();
+        assert_eq!(deadline, game.now + RECONNECT_GRACE_MILLIS);
+
+        let expire = |time| Event {
+            time,
+            kind: EventKind::SeatExpired { player_id: x },
+        };
+        assert!(game.push(expire(deadline - 1)).is_err());
+        let secret = game.player_secret(x).unwrap();
+        let player = game.reconnect(&secret).unwrap();
+        assert_eq!((player.id, player.disconnected), (x, None));
+        // Back in time, so the seat is no longer up for grabs
+        assert!(game.push(expire(deadline)).is_err());
+
+        game.disconnect_player(x);
+        let deadline = game.state.players[0].disconnected.unwrap();
+        assert!(game.push(expire(deadline)).is_ok());
+        assert_eq!(game.state.players.len(), 1);
+    }
+
+    #[test]
+    fn clocks_stop_while_the_player_to_move_is_away() {
+        let config = Config {
+            time_control: Some(TimeControl::PerMove { seconds: 30 }),
+            ..Config::default()
+        };
+        let (mut game, x, o) = two_players(config);
+        let start = game.now;
+        record_at(
+            &mut game,
+            start + 5_000,
+            EventKind::Disconnected { player_id: x },
+        );
+        assert!(!game.pass_time_at(start + 50_000));
+        record_at(
+            &mut game,
+            start + 50_000,
+            EventKind::Reconnected { player_id: x },
+        );
+        let clock = game.state.clock.as_ref().unwrap();
+        assert_eq!(clock.left_at('X', start + 50_000), 25_000);
+
+        // The clock only stops for the player to move
+        record_at(
+            &mut game,
+            start + 51_000,
+            EventKind::Disconnected { player_id: o },
+        );
+        assert_eq!(game.state.clock.as_ref().unwrap().x, 24_000);
+        assert!(game.next_timeout().is_some());
+        play_at(&mut game, start + 52_000, x, move_to(4));
+        assert_eq!(game.next_timeout(), None);
+        assert!(!game.pass_time_at(start + 200_000));
+        assert_eq!(game.state.winner, None);
+    }
+
+    #[test]
+    fn only_the_latest_secret_holds_a_seat() {
+        let (mut game, x, o) = two_players(Config::default());
+        let old = game.player_secret(x).unwrap();
+        assert!(game.authorize(x, &old).is_ok());
+        assert!(game.authorize(o, &old).is_err());
+        assert!(game.authorize(x, "").is_err());
+
+        game.disconnect_player(x);
+        game.reconnect(&old).unwrap();
+        let new = game.player_secret(x).unwrap();
+        assert_ne!(new, old);
+        assert!(game.authorize(x, &old).is_err());
+        assert!(game.authorize(x, &new).is_ok());
+        assert!(game.reconnect(&old).is_err());
+
+        game.remove_player(x);
+        assert!(game.authorize(x, &new).is_err());
+    }
+
+    #[test]
+    fn no_hints_in_rated_games() {
+        let (mut game, x, o) = two_players(Config::default());
+        assert!(matches!(
+            game.handle_msg(x, FromBrowser::RequestHint),
+            Ok(Response::Hint(_))
+        ));
+        game.link_profile(x, 1, 1200);
+        game.link_profile(o, 2, 1200);
+        assert!(game.is_rated());
+        game.hints_given.clear();
+        assert!(game.handle_msg(x, FromBrowser::RequestHint).is_err());
+
+        // Games against the computer are not rated
+        let (mut game, _) = Game::new("bot".to_string(), Config::default());
+        let x = game.add_player("A".to_string()).unwrap().id;
+        game.add_bot(Difficulty::Easy).unwrap();
+        game.link_profile(x, 1, 1200);
+        assert!(!game.is_rated());
+        assert!(game.handle_msg(x, FromBrowser::RequestHint).is_ok());
+    }
+
+    #[test]
+    fn clocks_wait_for_players_after_a_restart() {
+        let config = Config {
+            time_control: Some(TimeControl::PerMove { seconds: 30 }),
+            ..Config::default()
+        };
+        let (mut game, x, o) = two_players(config);
+        let start = game.now;
+        play_at(&mut game, start + 1_000, x, move_to(4));
+        record_at(&mut game, start + 10_000, EventKind::Restarted);
+        assert_eq!(game.next_timeout(), None);
+
+        // Only the player to move coming back starts the clock again
+        record_at(
+            &mut game,
+            start + 20_000,
+            EventKind::Reconnected { player_id: x },
+        );
+        assert_eq!(game.next_timeout(), None);
+        assert!(!game.pass_time_at(start + 90_000));
+        record_at(
+            &mut game,
+            start + 90_000,
+            EventKind::Reconnected { player_id: o },
+        );
+        let clock = game.state.clock.as_ref().unwrap();
+        assert_eq!(clock.left_at('O', start + 95_000), 25_000);
+        assert_eq!(game.state.winner, None);
+
+        // A computer player moves as soon as the server is back
+        let (mut game, _) = Game::new("bot".to_string(), config);
+        let x = game.add_player("A".to_string()).unwrap().id;
+        game.add_bot(Difficulty::Easy).unwrap();
+        let start = game.now;
+        play_at(&mut game, start + 1_000, x, move_to(4));
+        record_at(&mut game, start + 10_000, EventKind::Restarted);
+        let clock = game.state.clock.as_ref().unwrap();
+        assert_eq!(clock.started, Some(start + 10_000));
+    }
+
+    #[test]
+    fn long_names_are_cut_at_a_character() {
+        let (mut game, x, _) = two_players(Config::default());
+        let new_name = "é".repeat(40);
+        play(&mut game, x, FromBrowser::ChangeName { new_name });
+        assert_eq!(game.state.players[0].name, "é".repeat(32));
+        play(
+            &mut game,
+            x,
+            FromBrowser::ChangeName {
+                new_name: "  ".into(),
+            },
+        );
+        assert_eq!(game.state.players[0].name, "Unnamed Player");
+    }
+
+    #[test]
+    fn leaving_stops_the_clock() {
+        let config = Config {
+            time_control: Some(TimeControl::Total {
+                seconds: 60,
+                increment: 0,
+            }),
+            ..Config::default()
+        };
+        let (mut game, x, o) = two_players(config);
+        let start = game.now;
+        record_at(&mut game, start + 5_000, EventKind::Left { player_id: o });
+        let clock = game.state.clock.clone().unwrap();
+        assert_eq!((clock.x, clock.started), (55_000, None));
+
+        game.add_player("C".to_string()).unwrap();
+        assert!(game.state.clock.as_ref().unwrap().started.is_some());
+        assert_eq!(game.state.players[0].id, x);
+    }
+}
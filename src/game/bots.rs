@@ -0,0 +1,111 @@
+use super::{
+    engine::{self, Outcome},
+    Board, Config, Difficulty, EventKind, FromBrowser, Game, PlayerID, Target,
+};
+
+/// Something a computer player has to think about. Thinking takes a while on
+/// large boards, so it is done away from the game and the result handed back
+/// to `Game::play_bot`.
+#[derive(Debug, Clone)]
+pub struct BotTurn {
+    pub player_id: PlayerID,
+    task: Task,
+    config: Config,
+    board: Board,
+    /// Team to move.
+    turn: char,
+    team: char,
+    difficulty: Difficulty,
+    /// Events recorded when the turn was handed out, to tell whether the
+    /// game moved on in the meantime.
+    events: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Task {
+    Move,
+    /// The opponent offered a draw.
+    AnswerDraw,
+}
+
+impl BotTurn {
+    /// Works out what the computer player does. None if it has no legal
+    /// moves.
+    pub fn think(&self) -> Option<FromBrowser> {
+        let rules = self.config.rules();
+        match self.task {
+            Task::Move => {
+                let space = engine::choose_move(
+                    rules.as_ref(),
+                    &self.board,
+                    self.team,
+                    self.difficulty,
+                    &mut rand::thread_rng(),
+                )?;
+                Some(FromBrowser::Move(Target::space(space)))
+            }
+            Task::AnswerDraw => {
+                // Accept unless there is a win to play for
+                let winning = engine::analyze(rules.as_ref(), &self.board, self.turn).is_some_and(
+                    |analysis| {
+                        if self.turn == self.team {
+                            analysis.moves.iter().any(|m| m.outcome == Outcome::Win)
+                        } else {
+                            analysis.moves.iter().all(|m| m.outcome == Outcome::Loss)
+                        }
+                    },
+                );
+                if winning {
+                    Some(FromBrowser::DeclineDraw)
+                } else {
+                    Some(FromBrowser::AcceptDraw)
+                }
+            }
+        }
+    }
+}
+
+impl Game {
+    /// What a computer player has to do next, if anything: answer a draw
+    /// offer, or move if it is its turn.
+    pub fn bot_turn(&self) -> Option<BotTurn> {
+        if self.state.winner.is_some() || self.state.players.len() < 2 {
+            return None;
+        }
+        let (bot, task) = match self.state.draw_offer {
+            Some(from) => (
+                self.state.players.iter().find(|p| p.id != from)?,
+                Task::AnswerDraw,
+            ),
+            None => (
+                self.state
+                    .players
+                    .iter()
+                    .find(|p| p.team == self.state.turn)?,
+                Task::Move,
+            ),
+        };
+        Some(BotTurn {
+            player_id: bot.id,
+            task,
+            config: self.state.config,
+            board: self.state.board.clone(),
+            turn: self.state.turn,
+            team: bot.team,
+            difficulty: bot.bot?,
+            events: self.events.len(),
+        })
+    }
+
+    /// Plays what the computer player came up with on `turn`, unless the game
+    /// moved on while it was thinking.
+    pub fn play_bot(&mut self, turn: &BotTurn, msg: FromBrowser) -> Result<(), String> {
+        if turn.events != self.events.len() {
+            return Err("The game moved on".to_string());
+        }
+        self.record(EventKind::Message {
+            player_id: turn.player_id,
+            msg,
+        })
+    }
+}
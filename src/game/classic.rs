@@ -0,0 +1,106 @@
+use super::{rules::other_team, Board, Config, EndReason, EndState, Rules};
+
+/// An m,n,k-game: players take turns placing pieces anywhere on the board,
+/// and the first to get `win_length` in a row wins. With a depth above 1 the
+/// board is a cube and lines can run through the layers, as in Qubic.
+#[derive(Debug, Clone)]
+pub struct Classic {
+    width: usize,
+    height: usize,
+    depth: usize,
+    win_length: usize,
+    /// Every line a player could win with, for evaluating positions.
+    lines: Vec<Vec<usize>>,
+}
+
+impl Classic {
+    pub fn new(config: &Config) -> Classic {
+        let board = Board::new_3d(config.width, config.height, config.depth);
+        Classic {
+            width: config.width,
+            height: config.height,
+            depth: config.depth,
+            win_length: config.win_length,
+            lines: board.lines(config.win_length),
+        }
+    }
+}
+
+impl Rules for Classic {
+    fn new_board(&self) -> Board {
+        Board::new_3d(self.width, self.height, self.depth)
+    }
+
+    /// Only a line through the space just played can have been completed.
+    fn end_state(&self, board: &Board, team: char, space: usize) -> Option<EndState> {
+        let lines = board.lines_through(space, self.win_length);
+        if !lines.is_empty() {
+            Some(EndState::line(team, lines))
+        } else if board.is_full() {
+            Some(EndState::draw(EndReason::BoardFull))
+        } else {
+            None
+        }
+    }
+
+    /// A line holding pieces of both teams can never be completed.
+    fn line_possible(&self, board: &Board) -> bool {
+        self.lines.iter().any(|line| {
+            let mut pieces = line.iter().map(|&s| board.cells[s]).filter(|&c| c != ' ');
+            match pieces.next() {
+                Some(first) => pieces.all(|c| c == first),
+                None => true,
+            }
+        })
+    }
+
+    /// Lines still open to only one side are worth more the fuller they are.
+    fn evaluate(&self, board: &Board, team: char) -> i32 {
+        let opponent = other_team(team);
+        let mut score: i32 = 0;
+        for line in &self.lines {
+            let mine = line.iter().filter(|&&s| board.cells[s] == team).count();
+            let theirs = line.iter().filter(|&&s| board.cells[s] == opponent).count();
+            match (mine, theirs) {
+                (0, 0) => {}
+                (n, 0) => score = score.saturating_add(4i32.saturating_pow(n as u32)),
+                (0, n) => score = score.saturating_sub(4i32.saturating_pow(n as u32)),
+                _ => {}
+            }
+        }
+        score.clamp(-100_000_000, 100_000_000)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn board(cells: &str) -> Board {
+        let mut board = Board::new(3, 3);
+        board.cells = cells.chars().collect();
+        board
+    }
+
+    #[test]
+    fn completing_a_line_wins() {
+        let rules = Classic::new(&Config::default());
+        let end = rules.end_state(&board("XXXOO    "), 'X', 2);
+        assert_eq!(end, Some(EndState::line('X', vec![vec![0, 1, 2]])));
+        assert_eq!(rules.end_state(&board("XX OO    "), 'X', 1), None);
+    }
+
+    #[test]
+    fn a_full_board_without_a_line_is_a_draw() {
+        let rules = Classic::new(&Config::default());
+        let end = rules.end_state(&board("XOXXOOOXX"), 'X', 8);
+        assert_eq!(end, Some(EndState::draw(EndReason::BoardFull)));
+    }
+
+    #[test]
+    fn lines_with_both_teams_are_dead() {
+        let rules = Classic::new(&Config::default());
+        assert!(rules.line_possible(&board("XOX      ")));
+        assert!(!rules.line_possible(&board("XOXXOOOX ")));
+    }
+}
@@ -0,0 +1,156 @@
+use serde::{Deserialize, Serialize};
+
+/// How much time players get, chosen when the room is created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum TimeControl {
+    /// Every move has to be made within this many seconds.
+    PerMove { seconds: u64 },
+    /// Each player has this many seconds for the whole game, and gains
+    /// `increment` seconds after every move they make.
+    Total { seconds: u64, increment: u64 },
+}
+
+impl TimeControl {
+    /// Longest time a player can be given, one hour.
+    pub const MAX_SECONDS: u64 = 60 * 60;
+
+    pub fn validate(&self) -> Result<(), String> {
+        let (seconds, increment) = match *self {
+            TimeControl::PerMove { seconds } => (seconds, 0),
+            TimeControl::Total { seconds, increment } => (seconds, increment),
+        };
+        if !(1..=TimeControl::MAX_SECONDS).contains(&seconds) || increment > seconds {
+            return Err(format!(
+                "Time must be between 1 and {} seconds, with a smaller increment",
+                TimeControl::MAX_SECONDS
+            ));
+        }
+        Ok(())
+    }
+
+    fn initial_millis(&self) -> u64 {
+        match *self {
+            TimeControl::PerMove { seconds } | TimeControl::Total { seconds, .. } => seconds * 1000,
+        }
+    }
+}
+
+/// Time left for each side. Only the player to move loses time, from
+/// `started` on.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Clock {
+    /// Milliseconds X had left when `started` was last set.
+    pub x: u64,
+    /// Milliseconds O had left when `started` was last set.
+    pub o: u64,
+    /// Milliseconds since the Unix epoch when the clock of the player to move
+    /// last started. None while the clock is stopped.
+    pub started: Option<u64>,
+}
+
+impl Clock {
+    pub fn new(time_control: TimeControl) -> Clock {
+        let millis = time_control.initial_millis();
+        Clock {
+            x: millis,
+            o: millis,
+            started: None,
+        }
+    }
+
+    pub fn remaining(&self, team: char) -> u64 {
+        if team == 'X' {
+            self.x
+        } else {
+            self.o
+        }
+    }
+
+    fn remaining_mut(&mut self, team: char) -> &mut u64 {
+        if team == 'X' {
+            &mut self.x
+        } else {
+            &mut self.o
+        }
+    }
+
+    /// Charges `team` for the time since the clock last started and restarts
+    /// it at `now`. Returns the milliseconds `team` has left.
+    pub fn tick(&mut self, team: char, now: u64) -> u64 {
+        let started = self.started.replace(now).unwrap_or(now);
+        let left = self.remaining_mut(team);
+        *left = left.saturating_sub(now.saturating_sub(started));
+        *left
+    }
+
+    /// Credits `team` for having made a move.
+    pub fn moved(&mut self, team: char, time_control: TimeControl) {
+        let left = self.remaining_mut(team);
+        match time_control {
+            TimeControl::PerMove { seconds } => *left = seconds * 1000,
+            TimeControl::Total { increment, .. } => *left += increment * 1000,
+        }
+    }
+
+    /// Puts the time back after moves were taken back. With a time limit per
+    /// move both sides have a full move's time again, as the moves played
+    /// over are new ones. Time used from a total stays used.
+    pub fn took_back(&mut self, time_control: TimeControl) {
+        if let TimeControl::PerMove { .. } = time_control {
+            let millis = time_control.initial_millis();
+            self.x = millis;
+            self.o = millis;
+        }
+    }
+
+    /// Milliseconds `team` has left at `now`, if it is their clock running.
+    pub fn left_at(&self, team: char, now: u64) -> u64 {
+        let started = self.started.unwrap_or(now);
+        self.remaining(team)
+            .saturating_sub(now.saturating_sub(started))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_player_to_move_loses_time() {
+        let time_control = TimeControl::Total {
+            seconds: 60,
+            increment: 2,
+        };
+        let mut clock = Clock::new(time_control);
+        assert_eq!(clock.tick('X', 1_000), 60_000);
+        assert_eq!(clock.left_at('X', 11_000), 50_000);
+        assert_eq!(clock.tick('X', 11_000), 50_000);
+        clock.moved('X', time_control);
+        assert_eq!(clock.remaining('X'), 52_000);
+
+        assert_eq!(clock.tick('O', 16_000), 55_000);
+        assert_eq!(clock.remaining('X'), 52_000);
+        assert_eq!(clock.left_at('O', 100_000), 0);
+    }
+
+    #[test]
+    fn per_move_time_resets_after_each_move() {
+        let time_control = TimeControl::PerMove { seconds: 10 };
+        let mut clock = Clock::new(time_control);
+        clock.tick('X', 0);
+        assert_eq!(clock.tick('X', 7_000), 3_000);
+        clock.moved('X', time_control);
+        assert_eq!(clock.remaining('X'), 10_000);
+    }
+
+    #[test]
+    fn time_controls_are_checked() {
+        assert!(TimeControl::PerMove { seconds: 0 }.validate().is_err());
+        let increment_too_big = TimeControl::Total {
+            seconds: 5,
+            increment: 10,
+        };
+        assert!(increment_too_big.validate().is_err());
+        assert!(TimeControl::PerMove { seconds: 30 }.validate().is_ok());
+    }
+}
@@ -0,0 +1,450 @@
+use rand::{seq::SliceRandom, Rng};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+use super::{
+    mcts::{Budget, Mcts},
+    Board, EndState, Rules,
+};
+
+/// How hard a computer player tries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Difficulty {
+    /// Plays any legal move at random.
+    Easy,
+    /// Takes wins, blocks losses, and otherwise follows the rules' heuristic.
+    Medium,
+    /// Searches the game tree with alpha-beta pruning, which is perfect on
+    /// small boards, or with Monte-Carlo tree search if the rules prefer it
+    /// or there are too many moves to search well.
+    Hard,
+}
+
+/// Score of a won position. Heuristic scores stay well below it.
+const WIN: i32 = 1_000_000_000;
+
+/// Positions the hard search may visit per move, so large boards still
+/// answer promptly. It keeps the best move of the deepest finished pass.
+const NODE_BUDGET: usize = 100_000;
+
+/// Longest the hard search thinks about a move, however many positions it
+/// has left to visit.
+const THINKING_TIME: Duration = Duration::from_secs(1);
+
+/// Positions a hint may visit while trying to solve every move exactly,
+/// before settling for Monte-Carlo estimates.
+const ANALYSIS_BUDGET: usize = 200_000;
+
+/// Positions with more legal moves than this are left to Monte-Carlo tree
+/// search, which copes better with that many choices than alpha-beta does.
+const MCTS_THRESHOLD: usize = 64;
+
+/// Most empty spaces `solve` takes on. It runs after every move in rooms
+/// that end forced draws early, and larger positions would use up the whole
+/// analysis budget each time without an answer.
+const SOLVE_LIMIT: u32 = 10;
+
+/// Boards with more legal moves than this only consider moves next to
+/// pieces that are already down.
+const CANDIDATE_LIMIT: usize = 24;
+
+/// Picks a move for `team`, or None if it has no legal moves.
+pub fn choose_move(
+    rules: &dyn Rules,
+    board: &Board,
+    team: char,
+    difficulty: Difficulty,
+    rng: &mut impl Rng,
+) -> Option<usize> {
+    let mut moves = rules.legal_moves(board, team);
+    if moves.is_empty() {
+        return None;
+    }
+    moves.shuffle(rng);
+
+    match difficulty {
+        Difficulty::Easy => moves.first().copied(),
+        Difficulty::Medium => Some(heuristic_move(rules, board, team, &moves)),
+        Difficulty::Hard if rules.prefers_mcts() || moves.len() > MCTS_THRESHOLD => {
+            Mcts::new(rules, Budget::default(), Some(rng.gen())).best_move(board, team)
+        }
+        Difficulty::Hard => Some(search_move(rules, board, team, candidates(board, moves))),
+    }
+}
+
+/// The board after `team` plays `space`, and whether that ended the game.
+pub fn after_move(
+    rules: &dyn Rules,
+    board: &Board,
+    team: char,
+    space: usize,
+) -> (Board, Option<EndState>) {
+    let mut board = board.clone();
+    rules.play(&mut board, team, space);
+    let end = rules.end_state(&board, team, space);
+    (board, end)
+}
+
+fn heuristic_move(rules: &dyn Rules, board: &Board, team: char, moves: &[usize]) -> usize {
+    let opponent = super::rules::other_team(team);
+    let outcomes: Vec<(usize, Board, Option<EndState>)> = moves
+        .iter()
+        .map(|&space| {
+            let (board, end) = after_move(rules, board, team, space);
+            (space, board, end)
+        })
+        .collect();
+
+    if let Some((space, _, _)) = outcomes
+        .iter()
+        .find(|(_, _, end)| end.as_ref().and_then(EndState::winner) == Some(team))
+    {
+        return *space;
+    }
+
+    // A space the opponent would win on next turn, if it is ours to take
+    let block = moves.iter().copied().find(|&space| {
+        let (_, end) = after_move(rules, board, opponent, space);
+        end.as_ref().and_then(EndState::winner) == Some(opponent)
+    });
+    let safe = |space: &usize| {
+        outcomes.iter().any(|(s, _, end)| {
+            s == space && end.as_ref().and_then(EndState::winner) != Some(opponent)
+        })
+    };
+    if let Some(space) = block.filter(safe) {
+        return space;
+    }
+
+    outcomes
+        .iter()
+        .max_by_key(|(_, board, end)| match end.as_ref().map(EndState::winner) {
+            Some(Some(winner)) if winner == opponent => -WIN,
+            Some(_) => 0,
+            None => rules.evaluate(board, team),
+        })
+        .map(|(space, _, _)| *space)
+        .unwrap_or(moves[0])
+}
+
+/// On crowded boards only spaces touching an existing piece are worth
+/// searching. Falls back to every move on an empty board.
+fn candidates(board: &Board, moves: Vec<usize>) -> Vec<usize> {
+    if moves.len() <= CANDIDATE_LIMIT {
+        return moves;
+    }
+
+    let directions = board.directions();
+    let near: Vec<usize> = moves
+        .iter()
+        .copied()
+        .filter(|&space| {
+            let (x, y, z) = board.coords_3d(space);
+            let (x, y, z) = (x as isize, y as isize, z as isize);
+            directions.iter().any(|&(dx, dy, dz)| {
+                [-1, 1].into_iter().any(|sign| {
+                    board
+                        .space_at(x + sign * dx, y + sign * dy, z + sign * dz)
+                        .and_then(|next| board.get(next))
+                        .is_some_and(|c| c != ' ')
+                })
+            })
+        })
+        .collect();
+
+    if near.is_empty() {
+        moves
+    } else {
+        near
+    }
+}
+
+/// How a move turns out for the player making it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Outcome {
+    Win,
+    Draw,
+    Loss,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MoveAnalysis {
+    pub space: usize,
+    pub outcome: Outcome,
+}
+
+/// Every legal move for the side to play, and which one is best.
+#[derive(Debug, Clone, Serialize)]
+pub struct Analysis {
+    pub best: usize,
+    pub moves: Vec<MoveAnalysis>,
+    /// Whether the outcomes assume perfect play. Positions too large to solve
+    /// are estimated from random playouts instead.
+    pub exact: bool,
+}
+
+/// Works out how every legal move for `team` turns out. None if it has no
+/// legal moves.
+pub fn analyze(rules: &dyn Rules, board: &Board, team: char) -> Option<Analysis> {
+    let moves = rules.legal_moves(board, team);
+    if moves.is_empty() {
+        return None;
+    }
+
+    let depth = empty_spaces(board);
+    let mut search = Search::new(rules, ANALYSIS_BUDGET);
+    search.prune = false;
+    let solved: Option<Vec<(usize, i32)>> = moves
+        .iter()
+        .map(|&space| {
+            let score = search.score_move(board, team, space, depth, 1, (-WIN - 1, WIN + 1))?;
+            Some((space, score))
+        })
+        .collect();
+
+    if let Some(solved) = solved {
+        let best = solved.iter().max_by_key(|(_, score)| *score).unwrap().0;
+        let moves = solved
+            .into_iter()
+            .map(|(space, score)| MoveAnalysis {
+                space,
+                outcome: match score {
+                    s if s > 0 => Outcome::Win,
+                    0 => Outcome::Draw,
+                    _ => Outcome::Loss,
+                },
+            })
+            .collect();
+        return Some(Analysis {
+            best,
+            moves,
+            exact: true,
+        });
+    }
+
+    let stats = Mcts::new(rules, Budget::default(), None).search(board, team);
+    let best = stats.iter().max_by_key(|s| s.visits)?.space;
+    let moves = stats
+        .into_iter()
+        .map(|s| MoveAnalysis {
+            space: s.space,
+            outcome: match s.value {
+                v if v > 0.6 => Outcome::Win,
+                v if v < 0.4 => Outcome::Loss,
+                _ => Outcome::Draw,
+            },
+        })
+        .collect();
+    Some(Analysis {
+        best,
+        moves,
+        exact: false,
+    })
+}
+
+/// How the game turns out for `team`, to move next, if both sides play
+/// perfectly. None if the position is too large to solve.
+pub fn solve(rules: &dyn Rules, board: &Board, team: char) -> Option<Outcome> {
+    if empty_spaces(board) > SOLVE_LIMIT {
+        return None;
+    }
+    let mut search = Search::new(rules, ANALYSIS_BUDGET);
+    search.prune = false;
+    let score = search.best_score(board, team, empty_spaces(board), 1, (-WIN - 1, WIN + 1))?;
+    Some(match score {
+        s if s > 0 => Outcome::Win,
+        0 => Outcome::Draw,
+        _ => Outcome::Loss,
+    })
+}
+
+/// Moves left until the board is full, which is as deep as a game can go.
+fn empty_spaces(board: &Board) -> u32 {
+    board.cells.iter().filter(|&&c| c == ' ').count() as u32
+}
+
+fn search_move(rules: &dyn Rules, board: &Board, team: char, mut moves: Vec<usize>) -> usize {
+    let max_depth = empty_spaces(board);
+    let mut search = Search::new(rules, NODE_BUDGET);
+    search.deadline = Some(Instant::now() + THINKING_TIME);
+    let mut best = moves[0];
+
+    for depth in 1..=max_depth.max(1) {
+        let mut alpha = -WIN - 1;
+        let mut pass_best = None;
+        for &space in &moves {
+            let score = match search.score_move(board, team, space, depth, 1, (alpha, WIN + 1)) {
+                Some(score) => score,
+                None => break,
+            };
+            if score > alpha {
+                alpha = score;
+                pass_best = Some(space);
+            }
+        }
+
+        if search.nodes >= search.budget {
+            break;
+        }
+        if let Some(space) = pass_best {
+            best = space;
+            // Search the best move first on the next pass
+            moves.retain(|&m| m != space);
+            moves.insert(0, space);
+        }
+        if alpha.abs() >= WIN - max_depth as i32 {
+            // Forced result found, searching deeper cannot change it
+            break;
+        }
+    }
+
+    best
+}
+
+struct Search<'a> {
+    rules: &'a dyn Rules,
+    nodes: usize,
+    budget: usize,
+    /// When to stop even if there is budget left.
+    deadline: Option<Instant>,
+    /// Only look at `candidates` on crowded boards. Faster, but no longer
+    /// exact.
+    prune: bool,
+}
+
+impl<'a> Search<'a> {
+    fn new(rules: &'a dyn Rules, budget: usize) -> Search<'a> {
+        Search {
+            rules,
+            nodes: 0,
+            budget,
+            deadline: None,
+            prune: true,
+        }
+    }
+
+    /// Score for `team` of playing `space`, or None when out of budget.
+    /// Scores outside the (alpha, beta) window are not exact.
+    fn score_move(
+        &mut self,
+        board: &Board,
+        team: char,
+        space: usize,
+        depth: u32,
+        ply: i32,
+        (alpha, beta): (i32, i32),
+    ) -> Option<i32> {
+        self.nodes += 1;
+        if self.nodes.is_multiple_of(256) && self.deadline.is_some_and(|d| Instant::now() >= d) {
+            // Out of time, which counts as out of budget from here on
+            self.budget = self.nodes;
+        }
+        if self.nodes >= self.budget {
+            return None;
+        }
+
+        let (board, end) = after_move(self.rules, board, team, space);
+        match end.as_ref().map(EndState::winner) {
+            Some(Some(winner)) if winner == team => return Some(WIN - ply),
+            Some(Some(_)) => return Some(-(WIN - ply)),
+            Some(None) => return Some(0),
+            None => {}
+        }
+
+        let next = self.rules.next_turn(&board, team);
+        let sign = if next == team { 1 } else { -1 };
+        if depth <= 1 {
+            return Some(sign * self.rules.evaluate(&board, next));
+        }
+
+        // Negamax from the point of view of whoever moves next
+        let window = if sign == 1 {
+            (alpha, beta)
+        } else {
+            (-beta, -alpha)
+        };
+        let value = self.best_score(&board, next, depth - 1, ply + 1, window)?;
+        Some(sign * value)
+    }
+
+    fn best_score(
+        &mut self,
+        board: &Board,
+        team: char,
+        depth: u32,
+        ply: i32,
+        (mut alpha, beta): (i32, i32),
+    ) -> Option<i32> {
+        let mut moves = self.rules.legal_moves(board, team);
+        if self.prune {
+            moves = candidates(board, moves);
+        }
+        if moves.is_empty() {
+            return Some(0);
+        }
+
+        let mut best = -WIN - 1;
+        for space in moves {
+            let score = self.score_move(board, team, space, depth, ply, (alpha, beta))?;
+            best = best.max(score);
+            alpha = alpha.max(score);
+            if alpha >= beta {
+                break;
+            }
+        }
+        Some(best)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{rules::other_team, Config, Variant};
+    use rand::{rngs::StdRng, SeedableRng};
+
+    /// Plays `bot` for the hard computer player against every possible reply
+    /// from its opponent, failing if any of them wins.
+    fn never_loses(rules: &dyn Rules, board: &Board, team: char, bot: char, rng: &mut StdRng) {
+        let moves = if team == bot {
+            let space = choose_move(rules, board, team, Difficulty::Hard, rng).unwrap();
+            vec![space]
+        } else {
+            rules.legal_moves(board, team)
+        };
+        for space in moves {
+            match after_move(rules, board, team, space) {
+                (_, Some(end)) => assert_ne!(end.winner(), Some(other_team(bot)), "{:?}", board),
+                (board, None) => never_loses(rules, &board, other_team(team), bot, rng),
+            }
+        }
+    }
+
+    #[test]
+    fn hard_plays_3x3_perfectly() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for misere in [false, true] {
+            let config = Config {
+                misere,
+                ..Config::default()
+            };
+            let rules = config.rules();
+            let board = rules.new_board();
+            for bot in ['X', 'O'] {
+                never_loses(rules.as_ref(), &board, 'X', bot, &mut rng);
+            }
+        }
+    }
+
+    #[test]
+    fn solves_small_positions_only() {
+        let rules = Config::default().rules();
+        let mut board = rules.new_board();
+        assert_eq!(solve(rules.as_ref(), &board, 'X'), Some(Outcome::Draw));
+        board.cells = "XX OO    ".chars().collect();
+        assert_eq!(solve(rules.as_ref(), &board, 'X'), Some(Outcome::Win));
+
+        let rules = Config::new(Variant::Gravity, None, None, None, None, false).rules();
+        assert_eq!(solve(rules.as_ref(), &rules.new_board(), 'X'), None);
+    }
+}
@@ -0,0 +1,66 @@
+use serde::{Deserialize, Serialize};
+
+use super::{Difficulty, FromBrowser, PlayerID, ProfileID};
+
+/// Something that was done to a game. A game's state is what you get by
+/// applying its events in order to a new game, so events are never changed
+/// once recorded.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Event {
+    /// Milliseconds since the Unix epoch. Anything that depends on the time,
+    /// like clocks and offers running out, goes by this rather than the time
+    /// the event is applied.
+    pub time: u64,
+    pub kind: EventKind,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub enum EventKind {
+    /// Someone took the next free seat.
+    Joined {
+        name: String,
+        bot: Option<Difficulty>,
+    },
+    Left {
+        player_id: PlayerID,
+    },
+    Disconnected {
+        player_id: PlayerID,
+    },
+    Reconnected {
+        player_id: PlayerID,
+    },
+    /// A disconnected player's seat was given up, if they were still gone.
+    SeatExpired {
+        player_id: PlayerID,
+    },
+    StartedWatching {
+        name: String,
+    },
+    StoppedWatching {
+        spectator_id: PlayerID,
+    },
+    ProfileLinked {
+        player_id: PlayerID,
+        profile: ProfileID,
+        rating: i32,
+    },
+    Rated {
+        profile: ProfileID,
+        rating: i32,
+    },
+    /// A message from a player that changed the game, including the moves
+    /// and answers of computer players.
+    Message {
+        player_id: PlayerID,
+        msg: FromBrowser,
+    },
+    SpectatorMessage {
+        spectator_id: PlayerID,
+        msg: FromBrowser,
+    },
+    /// Time ran out for the player to move or for an offer.
+    TimePassed,
+    /// The server restarted, dropping every connection.
+    Restarted,
+}
@@ -0,0 +1,114 @@
+use super::{classic::Classic, Board, Config, EndState, Rules, Target};
+
+/// Connect Four: pieces are dropped into a column and fall to the lowest empty
+/// row. Otherwise played like `Classic`, by default on a 7x6 board with four
+/// in a row.
+#[derive(Debug, Clone)]
+pub struct Gravity {
+    classic: Classic,
+}
+
+impl Gravity {
+    pub fn new(config: &Config) -> Gravity {
+        Gravity {
+            classic: Classic::new(config),
+        }
+    }
+
+    /// Lowest empty space in a column, if it is not full.
+    fn drop_space(board: &Board, column: usize) -> Option<usize> {
+        if column >= board.width {
+            return None;
+        }
+        (0..board.height)
+            .rev()
+            .map(|y| y * board.width + column)
+            .find(|&space| board.cells[space] == ' ')
+    }
+}
+
+impl Rules for Gravity {
+    fn new_board(&self) -> Board {
+        self.classic.new_board()
+    }
+
+    /// Accepts a `column`, or a `space` anywhere in the column to drop into.
+    fn resolve(&self, board: &Board, target: &Target) -> Result<usize, String> {
+        let column = match (target.column, target.space) {
+            (Some(column), _) => column,
+            (None, Some(space)) if space < board.cells.len() => board.coords(space).0,
+            _ => return Err("Missing column".to_string()),
+        };
+        Gravity::drop_space(board, column).ok_or_else(|| "That column is full".to_string())
+    }
+
+    fn check_move(&self, board: &Board, _team: char, space: usize) -> Result<(), String> {
+        if space >= board.cells.len() {
+            return Err("Invalid move".to_string());
+        }
+        if Gravity::drop_space(board, board.coords(space).0) != Some(space) {
+            return Err("Invalid move".to_string());
+        }
+        Ok(())
+    }
+
+    fn legal_moves(&self, board: &Board, _team: char) -> Vec<usize> {
+        (0..board.width)
+            .filter_map(|column| Gravity::drop_space(board, column))
+            .collect()
+    }
+
+    fn end_state(&self, board: &Board, team: char, space: usize) -> Option<EndState> {
+        self.classic.end_state(board, team, space)
+    }
+
+    fn line_possible(&self, board: &Board) -> bool {
+        self.classic.line_possible(board)
+    }
+
+    fn evaluate(&self, board: &Board, team: char) -> i32 {
+        self.classic.evaluate(board, team)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{Config, Variant};
+
+    fn column(column: usize) -> Target {
+        Target {
+            column: Some(column),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn pieces_fall_to_the_lowest_empty_row() {
+        let config = Config::new(Variant::Gravity, None, None, None, None, false);
+        let rules = Gravity::new(&config);
+        let mut board = rules.new_board();
+        assert_eq!((board.width, board.height), (7, 6));
+
+        let bottom = 5 * 7 + 3;
+        assert_eq!(rules.resolve(&board, &column(3)), Ok(bottom));
+        rules.play(&mut board, 'X', bottom);
+        assert_eq!(rules.resolve(&board, &column(3)), Ok(bottom - 7));
+        // A space anywhere in the column drops the same way
+        assert_eq!(rules.resolve(&board, &Target::space(3)), Ok(bottom - 7));
+        assert!(rules.check_move(&board, 'O', 3).is_err());
+    }
+
+    #[test]
+    fn full_columns_take_no_more_pieces() {
+        let config = Config::new(Variant::Gravity, None, None, None, None, false);
+        let rules = Gravity::new(&config);
+        let mut board = rules.new_board();
+        for y in 0..6 {
+            rules.play(&mut board, 'X', y * 7);
+        }
+        assert!(rules.resolve(&board, &column(0)).is_err());
+        assert!(rules.resolve(&board, &column(7)).is_err());
+        assert_eq!(rules.legal_moves(&board, 'O').len(), 6);
+    }
+}
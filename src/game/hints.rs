@@ -0,0 +1,60 @@
+use super::{engine, Analysis, Board, Config, Game, PlayerID};
+
+/// Shortest time between two hints for the same seat, since working one out
+/// keeps a thread busy for a while.
+const HINT_COOLDOWN_MILLIS: u64 = 3_000;
+
+/// A hint a player asked for. Analysing the position takes a while, so it is
+/// done away from the game.
+#[derive(Debug, Clone)]
+pub struct HintRequest {
+    config: Config,
+    board: Board,
+    team: char,
+}
+
+impl HintRequest {
+    pub fn analyze(&self) -> Result<Analysis, String> {
+        let rules = self.config.rules();
+        engine::analyze(rules.as_ref(), &self.board, self.team)
+            .ok_or_else(|| "No legal moves".to_string())
+    }
+}
+
+impl Game {
+    /// Checks that `player_id` may have a hint now, for the position as it is.
+    pub(super) fn hint(&mut self, player_id: PlayerID) -> Result<HintRequest, String> {
+        if !self.state.config.hints {
+            return Err("Hints are disabled in this game".to_string());
+        }
+        // The engine must not play for either side of a game that is rated
+        if self.is_rated() {
+            return Err("Hints are off in rated games".to_string());
+        }
+        if self.state.winner.is_some() {
+            return Err("Game is over".to_string());
+        }
+        let player_idx = self
+            .get_player_index(player_id)
+            .ok_or("Invalid player ID")?;
+        let team = self.state.players[player_idx].team;
+        if self.state.turn != team {
+            return Err("Not your turn".to_string());
+        }
+
+        let now = super::now_millis();
+        match self.hints_given.get(&player_id) {
+            Some(&last) if now < last + HINT_COOLDOWN_MILLIS => {
+                return Err("Wait a moment before asking for another hint".to_string());
+            }
+            _ => {}
+        }
+        self.hints_given.insert(player_id, now);
+
+        Ok(HintRequest {
+            config: self.state.config,
+            board: self.state.board.clone(),
+            team,
+        })
+    }
+}
@@ -0,0 +1,257 @@
+use std::time::{Duration, Instant};
+
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+use super::{engine::after_move, rules::other_team, Board, EndState, Rules};
+
+/// How long a Monte-Carlo search may run. It stops at whichever limit comes
+/// first. With a seed and no time limit the result is always the same.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Budget {
+    pub iterations: usize,
+    pub time: Option<Duration>,
+}
+
+impl Default for Budget {
+    fn default() -> Self {
+        Budget {
+            iterations: 20_000,
+            time: Some(Duration::from_millis(500)),
+        }
+    }
+}
+
+/// What the search found out about one of the moves available at the root.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MoveStats {
+    pub space: usize,
+    pub visits: u32,
+    /// Average result for the player making the move: 1 is a win, 0.5 a
+    /// draw and 0 a loss.
+    pub value: f64,
+}
+
+/// Monte-Carlo tree search. Unlike the alpha-beta search it needs no
+/// heuristic from the rules, only random playouts, so it copes with boards
+/// far too large to search exhaustively.
+pub struct Mcts<'a> {
+    rules: &'a dyn Rules,
+    budget: Budget,
+    rng: StdRng,
+}
+
+/// Balances trying promising moves against trying rarely visited ones.
+const EXPLORATION: f64 = std::f64::consts::SQRT_2;
+
+struct Node {
+    /// Move leading to this node, None at the root.
+    space: Option<usize>,
+    /// Team that made that move.
+    team: char,
+    /// Team to move from this node.
+    to_move: char,
+    parent: Option<usize>,
+    children: Vec<usize>,
+    untried: Vec<usize>,
+    end: Option<EndState>,
+    visits: u32,
+    /// Sum of the results of every playout through here, for `team`.
+    reward: f64,
+}
+
+impl<'a> Mcts<'a> {
+    pub fn new(rules: &'a dyn Rules, budget: Budget, seed: Option<u64>) -> Mcts<'a> {
+        let rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        Mcts { rules, budget, rng }
+    }
+
+    /// Most visited move for `team`, or None if it has no legal moves.
+    pub fn best_move(&mut self, board: &Board, team: char) -> Option<usize> {
+        self.search(board, team)
+            .into_iter()
+            .max_by_key(|stats| stats.visits)
+            .map(|stats| stats.space)
+    }
+
+    /// Runs the search and reports on every legal move for `team`.
+    pub fn search(&mut self, board: &Board, team: char) -> Vec<MoveStats> {
+        let mut untried = self.rules.legal_moves(board, team);
+        untried.shuffle(&mut self.rng);
+        let mut nodes = vec![Node {
+            space: None,
+            team: other_team(team),
+            to_move: team,
+            parent: None,
+            children: Vec::new(),
+            untried,
+            end: None,
+            visits: 0,
+            reward: 0.0,
+        }];
+
+        let started = Instant::now();
+        for _ in 0..self.budget.iterations {
+            if nodes[0].untried.is_empty() && nodes[0].children.is_empty() {
+                break;
+            }
+            if let Some(time) = self.budget.time {
+                if started.elapsed() >= time {
+                    break;
+                }
+            }
+            self.iterate(&mut nodes, board);
+        }
+
+        nodes[0]
+            .children
+            .iter()
+            .map(|&child| {
+                let node = &nodes[child];
+                MoveStats {
+                    space: node.space.unwrap(),
+                    visits: node.visits,
+                    value: if node.visits == 0 {
+                        0.5
+                    } else {
+                        node.reward / node.visits as f64
+                    },
+                }
+            })
+            .collect()
+    }
+
+    fn iterate(&mut self, nodes: &mut Vec<Node>, root: &Board) {
+        let mut board = root.clone();
+        let mut current = 0;
+
+        // Selection: follow the best child until there is something new to try
+        while nodes[current].untried.is_empty()
+            && !nodes[current].children.is_empty()
+            && nodes[current].end.is_none()
+        {
+            let parent = &nodes[current];
+            let log_visits = (parent.visits.max(1) as f64).ln();
+            current = *parent
+                .children
+                .iter()
+                .max_by(|&&a, &&b| {
+                    uct(&nodes[a], log_visits)
+                        .partial_cmp(&uct(&nodes[b], log_visits))
+                        .unwrap()
+                })
+                .unwrap();
+            let node = &nodes[current];
+            self.rules.play(&mut board, node.team, node.space.unwrap());
+        }
+
+        // Expansion
+        if nodes[current].end.is_none() {
+            if let Some(space) = nodes[current].untried.pop() {
+                let team = nodes[current].to_move;
+                let (next_board, end) = after_move(self.rules, &board, team, space);
+                board = next_board;
+                let to_move = self.rules.next_turn(&board, team);
+                let mut untried = match end {
+                    Some(_) => Vec::new(),
+                    None => self.rules.legal_moves(&board, to_move),
+                };
+                untried.shuffle(&mut self.rng);
+
+                nodes.push(Node {
+                    space: Some(space),
+                    team,
+                    to_move,
+                    parent: Some(current),
+                    children: Vec::new(),
+                    untried,
+                    end,
+                    visits: 0,
+                    reward: 0.0,
+                });
+                let child = nodes.len() - 1;
+                nodes[current].children.push(child);
+                current = child;
+            }
+        }
+
+        let result = match &nodes[current].end {
+            Some(end) => Some(end.clone()),
+            None => self.playout(board, nodes[current].to_move),
+        };
+
+        // Backpropagation
+        let mut next = Some(current);
+        while let Some(index) = next {
+            let node = &mut nodes[index];
+            node.visits += 1;
+            node.reward += match result.as_ref().and_then(EndState::winner) {
+                Some(winner) if winner == node.team => 1.0,
+                Some(_) => 0.0,
+                None => 0.5,
+            };
+            next = node.parent;
+        }
+    }
+
+    /// Plays random moves to the end of the game. None if nobody can move.
+    fn playout(&mut self, mut board: Board, mut team: char) -> Option<EndState> {
+        loop {
+            let moves = self.rules.legal_moves(&board, team);
+            let space = *moves.get(self.rng.gen_range(0..moves.len().max(1)))?;
+            self.rules.play(&mut board, team, space);
+            if let Some(end) = self.rules.end_state(&board, team, space) {
+                return Some(end);
+            }
+            team = self.rules.next_turn(&board, team);
+        }
+    }
+}
+
+fn uct(node: &Node, log_parent_visits: f64) -> f64 {
+    if node.visits == 0 {
+        return f64::INFINITY;
+    }
+    let visits = node.visits as f64;
+    node.reward / visits + EXPLORATION * (log_parent_visits / visits).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::Config;
+
+    fn board(cells: &str) -> Board {
+        let mut board = Board::new(3, 3);
+        board.cells = cells.chars().collect();
+        board
+    }
+
+    #[test]
+    fn seeded_search_is_repeatable() {
+        let rules = Config::default().rules();
+        let budget = Budget {
+            iterations: 2_000,
+            time: None,
+        };
+        let board = board("X   O    ");
+        let first = Mcts::new(rules.as_ref(), budget, Some(7)).search(&board, 'X');
+        let second = Mcts::new(rules.as_ref(), budget, Some(7)).search(&board, 'X');
+        assert_eq!(first, second);
+        assert_eq!(first.len(), 7);
+    }
+
+    #[test]
+    fn finds_a_winning_move() {
+        let rules = Config::default().rules();
+        let budget = Budget {
+            iterations: 2_000,
+            time: None,
+        };
+        let board = board("XX OO    ");
+        let best = Mcts::new(rules.as_ref(), budget, Some(1)).best_move(&board, 'X');
+        assert_eq!(best, Some(2));
+    }
+}
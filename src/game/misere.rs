@@ -0,0 +1,113 @@
+use super::{rules::other_team, Board, EndState, Rules, SubBoards, Target};
+
+/// Wraps another rule set so that completing a line loses instead of wins.
+#[derive(Debug)]
+pub struct Misere {
+    inner: Box<dyn Rules>,
+}
+
+impl Misere {
+    pub fn new(inner: Box<dyn Rules>) -> Misere {
+        Misere { inner }
+    }
+}
+
+impl Rules for Misere {
+    fn new_board(&self) -> Board {
+        self.inner.new_board()
+    }
+
+    fn first_turn(&self) -> char {
+        self.inner.first_turn()
+    }
+
+    fn resolve(&self, board: &Board, target: &Target) -> Result<usize, String> {
+        self.inner.resolve(board, target)
+    }
+
+    fn check_move(&self, board: &Board, team: char, space: usize) -> Result<(), String> {
+        self.inner.check_move(board, team, space)
+    }
+
+    fn legal_moves(&self, board: &Board, team: char) -> Vec<usize> {
+        self.inner.legal_moves(board, team)
+    }
+
+    fn play(&self, board: &mut Board, team: char, space: usize) {
+        self.inner.play(board, team, space)
+    }
+
+    fn next_turn(&self, board: &Board, team: char) -> char {
+        self.inner.next_turn(board, team)
+    }
+
+    /// Whoever the inner rules say won, their opponent did.
+    fn end_state(&self, board: &Board, team: char, space: usize) -> Option<EndState> {
+        match self.inner.end_state(board, team, space)? {
+            EndState::Win {
+                team,
+                reason,
+                lines,
+            } => Some(EndState::Win {
+                team: other_team(team),
+                reason,
+                lines,
+            }),
+            draw => Some(draw),
+        }
+    }
+
+    /// Nobody can lose once no line can be completed either.
+    fn line_possible(&self, board: &Board) -> bool {
+        self.inner.line_possible(board)
+    }
+
+    /// Whatever would be good under the normal rules is bad here.
+    fn evaluate(&self, board: &Board, team: char) -> i32 {
+        -self.inner.evaluate(board, team)
+    }
+
+    fn prefers_mcts(&self) -> bool {
+        self.inner.prefers_mcts()
+    }
+
+    fn describe_space(&self, board: &Board, space: usize) -> String {
+        self.inner.describe_space(board, space)
+    }
+
+    fn sub_boards(&self, board: &Board) -> Option<SubBoards> {
+        self.inner.sub_boards(board)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{Config, EndReason};
+
+    #[test]
+    fn completing_a_line_loses() {
+        let config = Config {
+            misere: true,
+            ..Config::default()
+        };
+        let rules = config.rules();
+        let mut board = rules.new_board();
+        board.cells = "XXXOO    ".chars().collect();
+        let end = rules.end_state(&board, 'X', 2).unwrap();
+        assert_eq!(end.winner(), Some('O'));
+    }
+
+    #[test]
+    fn draws_stay_draws() {
+        let config = Config {
+            misere: true,
+            ..Config::default()
+        };
+        let rules = config.rules();
+        let mut board = rules.new_board();
+        board.cells = "XOXXOOOXX".chars().collect();
+        let end = rules.end_state(&board, 'X', 8);
+        assert_eq!(end, Some(EndState::draw(EndReason::BoardFull)));
+    }
+}
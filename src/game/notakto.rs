@@ -0,0 +1,133 @@
+use super::{
+    rules::{complete_lines, other_team, three_in_a_row},
+    Board, Config, EndState, Rules,
+};
+
+/// Notakto: both players place X on one or more 3x3 boards laid out side by
+/// side. A board is dead once it has three in a row, and nobody may play on
+/// it again. Whoever kills the last live board loses.
+#[derive(Debug, Clone)]
+pub struct Notakto {
+    boards: usize,
+}
+
+impl Notakto {
+    pub fn new(config: &Config) -> Notakto {
+        Notakto {
+            boards: config.boards,
+        }
+    }
+
+    /// Space on the combined board of `cell` (0 to 8) within board `n`.
+    fn space(board: &Board, n: usize, cell: usize) -> usize {
+        (cell / 3) * board.width + n * 3 + cell % 3
+    }
+
+    fn is_dead(board: &Board, n: usize) -> bool {
+        three_in_a_row(|cell| {
+            Some(board.cells[Notakto::space(board, n, cell)]).filter(|&c| c != ' ')
+        })
+        .is_some()
+    }
+}
+
+impl Rules for Notakto {
+    fn new_board(&self) -> Board {
+        Board::new(self.boards * 3, 3)
+    }
+
+    fn check_move(&self, board: &Board, _team: char, space: usize) -> Result<(), String> {
+        if board.get(space) != Some(' ') {
+            return Err("Invalid move".to_string());
+        }
+        if Notakto::is_dead(board, board.coords(space).0 / 3) {
+            return Err("That board is dead".to_string());
+        }
+        Ok(())
+    }
+
+    fn play(&self, board: &mut Board, _team: char, space: usize) {
+        board.place(space, 'X');
+    }
+
+    /// Boards can only die, never fill up without dying, so there are no draws.
+    /// The lines reported are the ones that killed the last board.
+    fn end_state(&self, board: &Board, team: char, space: usize) -> Option<EndState> {
+        if !(0..self.boards).all(|n| Notakto::is_dead(board, n)) {
+            return None;
+        }
+
+        let n = board.coords(space).0 / 3;
+        let lines = complete_lines(|cell| {
+            Some(board.cells[Notakto::space(board, n, cell)]).filter(|&c| c != ' ')
+        })
+        .iter()
+        .map(|line| {
+            line.iter()
+                .map(|&cell| Notakto::space(board, n, cell))
+                .collect()
+        })
+        .collect();
+        Some(EndState::line(other_team(team), lines))
+    }
+
+    fn describe_space(&self, board: &Board, space: usize) -> String {
+        let (x, y) = board.coords(space);
+        if self.boards == 1 {
+            format!("({}, {})", x + 1, y + 1)
+        } else {
+            format!("({}, {}) on board {}", x % 3 + 1, y + 1, x / 3 + 1)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{EndReason, Variant};
+
+    fn rules(boards: usize) -> Notakto {
+        Notakto::new(&Config::new(
+            Variant::Notakto,
+            None,
+            None,
+            None,
+            Some(boards),
+            false,
+        ))
+    }
+
+    #[test]
+    fn killing_the_last_board_loses() {
+        let rules = rules(1);
+        let mut board = rules.new_board();
+        for space in [0, 1] {
+            rules.play(&mut board, 'X', space);
+        }
+        rules.play(&mut board, 'O', 2);
+        assert_eq!(board.cells[2], 'X');
+
+        let end = rules.end_state(&board, 'O', 2).unwrap();
+        assert_eq!(end.winner(), Some('X'));
+        assert!(matches!(
+            end,
+            EndState::Win {
+                reason: EndReason::Line,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn dead_boards_take_no_more_moves() {
+        let rules = rules(2);
+        let mut board = rules.new_board();
+        for space in [0, 1, 2] {
+            rules.play(&mut board, 'X', space);
+        }
+        // The second board is still alive
+        assert_eq!(rules.end_state(&board, 'X', 2), None);
+        assert!(rules.check_move(&board, 'O', 6).is_err());
+        assert!(rules.check_move(&board, 'O', 3).is_ok());
+    }
+}
@@ -0,0 +1,166 @@
+use std::fmt::Debug;
+
+use serde::{Deserialize, Serialize};
+
+use super::{Board, Config, EndState, SubBoards, Target};
+
+/// Everything about how a particular kind of game is played. `Game` owns the
+/// players, chat and win counts, and asks its rules whether a move is legal,
+/// whose turn is next and whether the game is over.
+///
+/// Rules only ever look at the board, so engines can try moves on a cloned
+/// board without dragging the rest of the `State` along.
+pub trait Rules: Debug + Send + Sync {
+    /// The board at the start of every game, including rematches.
+    fn new_board(&self) -> Board;
+
+    /// Team that moves first on a new board.
+    fn first_turn(&self) -> char {
+        'X'
+    }
+
+    /// Works out which space a move request refers to. It is checked
+    /// separately.
+    fn resolve(&self, board: &Board, target: &Target) -> Result<usize, String> {
+        match (target.x, target.y) {
+            (Some(x), Some(y)) => board
+                .space_at(x as isize, y as isize, target.z.unwrap_or(0) as isize)
+                .ok_or_else(|| "Invalid move".to_string()),
+            _ => target.space.ok_or_else(|| "Missing space".to_string()),
+        }
+    }
+
+    /// Returns an error describing why `team` may not play on `space`.
+    fn check_move(&self, board: &Board, team: char, space: usize) -> Result<(), String> {
+        let _ = team;
+        if board.get(space) != Some(' ') {
+            return Err("Invalid move".to_string());
+        }
+        Ok(())
+    }
+
+    /// Every space `team` could legally play on.
+    fn legal_moves(&self, board: &Board, team: char) -> Vec<usize> {
+        (0..board.cells.len())
+            .filter(|&space| self.check_move(board, team, space).is_ok())
+            .collect()
+    }
+
+    /// Puts the piece for `team` on `space`. The move has already been checked.
+    fn play(&self, board: &mut Board, team: char, space: usize) {
+        board.place(space, team);
+    }
+
+    /// Team to move after `team` has played.
+    fn next_turn(&self, board: &Board, team: char) -> char {
+        let _ = board;
+        other_team(team)
+    }
+
+    /// Checks whether the move `team` just made on `space` ended the game.
+    fn end_state(&self, board: &Board, team: char, space: usize) -> Option<EndState>;
+
+    /// Whether either side could still complete a line. Variants that always
+    /// end in a win or a loss keep the default.
+    fn line_possible(&self, board: &Board) -> bool {
+        let _ = board;
+        true
+    }
+
+    /// Rough idea of how good an unfinished position is for `team`, used by
+    /// computer players when they cannot search to the end of the game.
+    /// Positive is good, and it should stay within a few million either way.
+    fn evaluate(&self, board: &Board, team: char) -> i32 {
+        let _ = (board, team);
+        0
+    }
+
+    /// Whether computer players should search with Monte-Carlo playouts
+    /// rather than alpha-beta, for games `evaluate` cannot judge well.
+    fn prefers_mcts(&self) -> bool {
+        false
+    }
+
+    /// Human readable position of a space, used in the chat log.
+    fn describe_space(&self, board: &Board, space: usize) -> String {
+        let (x, y, z) = board.coords_3d(space);
+        if board.depth > 1 {
+            format!("({}, {}, {})", x + 1, y + 1, z + 1)
+        } else {
+            format!("({}, {})", x + 1, y + 1)
+        }
+    }
+
+    /// Results of the smaller boards, for variants played across several.
+    fn sub_boards(&self, board: &Board) -> Option<SubBoards> {
+        let _ = board;
+        None
+    }
+}
+
+/// Every way to get three in a row on a 3x3 grid.
+const LINES: [[usize; 3]; 8] = [
+    [0, 1, 2],
+    [3, 4, 5],
+    [6, 7, 8],
+    [0, 3, 6],
+    [1, 4, 7],
+    [2, 5, 8],
+    [0, 4, 8],
+    [2, 4, 6],
+];
+
+/// Team holding a whole line of a 3x3 grid, where `owner` says who holds
+/// each of its nine cells.
+pub fn three_in_a_row(owner: impl Fn(usize) -> Option<char>) -> Option<char> {
+    let line = complete_lines(&owner).into_iter().next()?;
+    owner(line[0])
+}
+
+/// Every line of a 3x3 grid held entirely by one team, as cell numbers.
+pub fn complete_lines(owner: impl Fn(usize) -> Option<char>) -> Vec<[usize; 3]> {
+    LINES
+        .iter()
+        .filter(|line| {
+            let team = owner(line[0]);
+            team.is_some() && line[1..].iter().all(|&i| owner(i) == team)
+        })
+        .copied()
+        .collect()
+}
+
+pub fn other_team(team: char) -> char {
+    if team == 'X' {
+        'O'
+    } else {
+        'X'
+    }
+}
+
+/// The rule sets a room can be created with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Variant {
+    /// Get `win_length` in a row on a `width` x `height` board.
+    #[default]
+    Classic,
+    /// Nine 3x3 boards, where each move picks the board the opponent plays in.
+    Ultimate,
+    /// Connect Four: moves pick a column and fall to the bottom.
+    Gravity,
+    /// Both players play X on `boards` 3x3 boards, and completing a line on
+    /// the last live board loses.
+    Notakto,
+    /// Four in a row on a 4x4x4 cube, through any of its 76 lines.
+    Qubic,
+}
+
+impl Variant {
+    pub fn rules(&self, config: &Config) -> Box<dyn Rules> {
+        match self {
+            Variant::Classic | Variant::Qubic => Box::new(super::classic::Classic::new(config)),
+            Variant::Ultimate => Box::new(super::ultimate::Ultimate),
+            Variant::Gravity => Box::new(super::gravity::Gravity::new(config)),
+            Variant::Notakto => Box::new(super::notakto::Notakto::new(config)),
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use serde::{Deserialize, Serialize};
+
+use super::PlayerID;
+
+/// A best-of-N match. It is decided once one player has won more games than
+/// are left to play, or when every game has been played.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Series {
+    pub best_of: usize,
+    /// Finished games, oldest first.
+    pub games: Vec<SeriesGame>,
+    pub finished: bool,
+    /// None while the series is undecided, or if it ended level.
+    pub winner: Option<PlayerID>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SeriesGame {
+    /// Player who moved first.
+    pub started: PlayerID,
+    /// None for a draw.
+    pub winner: Option<PlayerID>,
+}
+
+impl Series {
+    pub fn new(best_of: usize) -> Series {
+        Series {
+            best_of,
+            games: Vec::new(),
+            finished: false,
+            winner: None,
+        }
+    }
+
+    /// Games won by `player` so far.
+    pub fn wins(&self, player: PlayerID) -> usize {
+        self.games
+            .iter()
+            .filter(|g| g.winner == Some(player))
+            .count()
+    }
+
+    /// Records a finished game between `players` and works out whether that
+    /// decided the series.
+    pub fn record(&mut self, game: SeriesGame, players: [PlayerID; 2]) {
+        if self.finished {
+            return;
+        }
+        self.games.push(game);
+
+        let left = self.best_of.saturating_sub(self.games.len());
+        let [a, b] = players.map(|p| self.wins(p));
+        if a.abs_diff(b) > left || left == 0 {
+            self.finished = true;
+            self.winner = match a.cmp(&b) {
+                std::cmp::Ordering::Greater => Some(players[0]),
+                std::cmp::Ordering::Less => Some(players[1]),
+                std::cmp::Ordering::Equal => None,
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn game(started: PlayerID, winner: Option<PlayerID>) -> SeriesGame {
+        SeriesGame { started, winner }
+    }
+
+    #[test]
+    fn decided_once_the_trailing_player_cannot_catch_up() {
+        let mut series = Series::new(5);
+        series.record(game(0, Some(0)), [0, 1]);
+        series.record(game(1, Some(0)), [0, 1]);
+        assert!(!series.finished);
+        series.record(game(0, Some(0)), [0, 1]);
+        assert!(series.finished);
+        assert_eq!(series.winner, Some(0));
+
+        // Games after the series is decided do not count
+        series.record(game(1, Some(1)), [0, 1]);
+        assert_eq!(series.games.len(), 3);
+    }
+
+    #[test]
+    fn level_after_every_game_has_no_winner() {
+        let mut series = Series::new(3);
+        series.record(game(0, None), [0, 1]);
+        series.record(game(1, Some(0)), [0, 1]);
+        series.record(game(0, Some(1)), [0, 1]);
+        assert!(series.finished);
+        assert_eq!(series.winner, None);
+        assert_eq!((series.wins(0), series.wins(1)), (1, 1));
+    }
+}
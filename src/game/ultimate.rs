@@ -0,0 +1,254 @@
+use super::{
+    rules::{complete_lines, three_in_a_row},
+    Board, EndReason, EndState, Rules, SubBoards, Target,
+};
+
+/// Ultimate tic-tac-toe: a 3x3 grid of 3x3 boards on one 9x9 `Board`. Winning
+/// a small board claims that cell of the big board, and the cell you play in
+/// sends your opponent to the matching small board. If that board is already
+/// decided they may play anywhere.
+///
+/// Small boards are numbered 0 to 8 row by row, and so are the cells within
+/// each of them.
+#[derive(Debug, Clone, Default)]
+pub struct Ultimate;
+
+impl Ultimate {
+    /// Space on the 9x9 board of `cell` within `sub_board`.
+    pub fn space(sub_board: usize, cell: usize) -> usize {
+        let x = (sub_board % 3) * 3 + cell % 3;
+        let y = (sub_board / 3) * 3 + cell / 3;
+        y * 9 + x
+    }
+
+    /// The small board a space belongs to, and its cell within it.
+    pub fn locate(space: usize) -> (usize, usize) {
+        let (x, y) = (space % 9, space / 9);
+        ((y / 3) * 3 + x / 3, (y % 3) * 3 + x % 3)
+    }
+
+    fn result(board: &Board, sub_board: usize) -> Option<EndState> {
+        let cells: Vec<char> = (0..9)
+            .map(|cell| board.cells[Ultimate::space(sub_board, cell)])
+            .collect();
+
+        let lines = complete_lines(|i| Some(cells[i]).filter(|&c| c != ' '));
+        if let Some(line) = lines.first() {
+            let team = cells[line[0]];
+            let lines = lines
+                .iter()
+                .map(|line| {
+                    line.iter()
+                        .map(|&cell| Ultimate::space(sub_board, cell))
+                        .collect()
+                })
+                .collect();
+            Some(EndState::line(team, lines))
+        } else if cells.iter().all(|&c| c != ' ') {
+            Some(EndState::draw(EndReason::BoardFull))
+        } else {
+            None
+        }
+    }
+
+    fn results(board: &Board) -> Vec<Option<EndState>> {
+        (0..9).map(|b| Ultimate::result(board, b)).collect()
+    }
+
+    /// The small board the next move must be made in, if any.
+    fn forced(board: &Board, results: &[Option<EndState>]) -> Option<usize> {
+        let (_, cell) = Ultimate::locate(board.last_move?);
+        if results[cell].is_some() {
+            None
+        } else {
+            Some(cell)
+        }
+    }
+}
+
+impl Rules for Ultimate {
+    fn new_board(&self) -> Board {
+        Board::new(9, 9)
+    }
+
+    /// Accepts either a space on the whole 9x9 board, or a small board and a
+    /// cell within it.
+    fn resolve(&self, _board: &Board, target: &Target) -> Result<usize, String> {
+        let space = target.space.ok_or("Missing space")?;
+        match target.sub_board {
+            Some(sub_board) if sub_board < 9 && space < 9 => Ok(Ultimate::space(sub_board, space)),
+            Some(_) => Err("Invalid move".to_string()),
+            None => Ok(space),
+        }
+    }
+
+    fn check_move(&self, board: &Board, _team: char, space: usize) -> Result<(), String> {
+        if board.get(space) != Some(' ') {
+            return Err("Invalid move".to_string());
+        }
+
+        let results = Ultimate::results(board);
+        let (sub_board, _) = Ultimate::locate(space);
+        if results[sub_board].is_some() {
+            return Err("That board is already decided".to_string());
+        }
+        if let Some(forced) = Ultimate::forced(board, &results) {
+            if forced != sub_board {
+                return Err("You must play in the highlighted board".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// Same as checking every space, but only works out the small board
+    /// results once.
+    fn legal_moves(&self, board: &Board, _team: char) -> Vec<usize> {
+        let results = Ultimate::results(board);
+        let forced = Ultimate::forced(board, &results);
+        (0..board.cells.len())
+            .filter(|&space| {
+                let (sub_board, _) = Ultimate::locate(space);
+                board.cells[space] == ' '
+                    && results[sub_board].is_none()
+                    && forced.is_none_or(|f| f == sub_board)
+            })
+            .collect()
+    }
+
+    /// The lines reported are the ones that won each small board in the
+    /// winning row of boards.
+    fn end_state(&self, board: &Board, _team: char, _space: usize) -> Option<EndState> {
+        let results = Ultimate::results(board);
+        let owner = |b: usize| results[b].as_ref().and_then(EndState::winner);
+        let rows = complete_lines(owner);
+
+        if let Some(row) = rows.first() {
+            let team = owner(row[0]).unwrap();
+            let lines = rows
+                .iter()
+                .flatten()
+                .filter_map(|&b| match &results[b] {
+                    Some(EndState::Win { lines, .. }) => Some(lines.clone()),
+                    _ => None,
+                })
+                .flatten()
+                .collect();
+            Some(EndState::line(team, lines))
+        } else if results.iter().all(|r| r.is_some()) {
+            Some(EndState::draw(EndReason::BoardFull))
+        } else {
+            None
+        }
+    }
+
+    /// A team can still win if some line of small boards holds only boards
+    /// they won or that are still undecided.
+    fn line_possible(&self, board: &Board) -> bool {
+        let results = Ultimate::results(board);
+        ['X', 'O'].into_iter().any(|team| {
+            let open = three_in_a_row(|b| match &results[b] {
+                None => Some(team),
+                Some(result) => result.winner().filter(|&winner| winner == team),
+            });
+            open.is_some()
+        })
+    }
+
+    /// Small boards won, with the centre worth more than the corners.
+    fn evaluate(&self, board: &Board, team: char) -> i32 {
+        Ultimate::results(board)
+            .iter()
+            .enumerate()
+            .map(|(sub_board, result)| {
+                let weight = match sub_board {
+                    4 => 3,
+                    0 | 2 | 6 | 8 => 2,
+                    _ => 1,
+                };
+                match result.as_ref().and_then(EndState::winner) {
+                    Some(winner) if winner == team => 100 * weight,
+                    Some(_) => -100 * weight,
+                    None => 0,
+                }
+            })
+            .sum()
+    }
+
+    /// Which small board the opponent gets sent to matters more than the
+    /// count of boards won, so playouts judge positions better.
+    fn prefers_mcts(&self) -> bool {
+        true
+    }
+
+    fn describe_space(&self, _board: &Board, space: usize) -> String {
+        let (sub_board, cell) = Ultimate::locate(space);
+        format!(
+            "({}, {}) in board ({}, {})",
+            cell % 3 + 1,
+            cell / 3 + 1,
+            sub_board % 3 + 1,
+            sub_board / 3 + 1
+        )
+    }
+
+    fn sub_boards(&self, board: &Board) -> Option<SubBoards> {
+        let results = Ultimate::results(board);
+        let forced = Ultimate::forced(board, &results);
+        Some(SubBoards { results, forced })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn after(moves: &[(char, usize, usize)]) -> Board {
+        let mut board = Ultimate.new_board();
+        for &(team, sub_board, cell) in moves {
+            let space = Ultimate::space(sub_board, cell);
+            Ultimate.check_move(&board, team, space).unwrap();
+            Ultimate.play(&mut board, team, space);
+        }
+        board
+    }
+
+    #[test]
+    fn spaces_and_cells_match_up() {
+        for space in 0..81 {
+            let (sub_board, cell) = Ultimate::locate(space);
+            assert_eq!(Ultimate::space(sub_board, cell), space);
+        }
+        assert_eq!(Ultimate::space(4, 4), 40);
+    }
+
+    #[test]
+    fn the_cell_played_picks_the_next_board() {
+        let board = after(&[('X', 0, 5)]);
+        let moves = Ultimate.legal_moves(&board, 'O');
+        assert_eq!(moves.len(), 9);
+        assert!(moves.iter().all(|&s| Ultimate::locate(s).0 == 5));
+        let elsewhere = Ultimate::space(3, 0);
+        assert!(Ultimate.check_move(&board, 'O', elsewhere).is_err());
+    }
+
+    #[test]
+    fn a_decided_board_frees_the_next_move() {
+        let mut board = after(&[('X', 0, 0), ('O', 0, 3), ('X', 3, 0), ('O', 0, 4)]);
+        for cell in [1, 2] {
+            board.cells[Ultimate::space(0, cell)] = 'X';
+        }
+        assert_eq!(
+            Ultimate::result(&board, 0).and_then(|r| r.winner()),
+            Some('X')
+        );
+
+        // Sent to board 0, which X already won, so O may play anywhere else
+        board.last_move = Some(Ultimate::space(8, 0));
+        let moves = Ultimate.legal_moves(&board, 'O');
+        assert!(moves.iter().all(|&s| Ultimate::locate(s).0 != 0));
+        assert!(moves.iter().any(|&s| Ultimate::locate(s).0 == 5));
+        assert!(Ultimate
+            .check_move(&board, 'O', Ultimate::space(0, 8))
+            .is_err());
+    }
+}
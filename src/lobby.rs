@@ -0,0 +1,89 @@
+use crate::game::{Game, Variant};
+use crate::AppState;
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        State,
+    },
+    response::Response,
+    Json,
+};
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use tokio::time::{sleep, Duration};
+use tracing::debug;
+
+/// A public room as shown in the lobby.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Room {
+    pub token: String,
+    pub variant: Variant,
+    pub players: Vec<String>,
+    pub open_seats: usize,
+    pub spectators: usize,
+}
+
+impl Room {
+    fn new(game: &Game) -> Room {
+        Room {
+            token: game.id.clone(),
+            variant: game.state.config.variant,
+            players: game.state.players.iter().map(|p| p.name.clone()).collect(),
+            open_seats: 2 - game.state.players.len().min(2),
+            spectators: game.state.spectators.len(),
+        }
+    }
+}
+
+/// Every room that asked to be listed, by token.
+fn public_rooms(state: &AppState) -> Vec<Room> {
+    // Let go of the room list before locking any game, as sockets lock a
+    // game first and then the list
+    let games: Vec<Arc<Mutex<Game>>> = state.games.lock().unwrap().values().cloned().collect();
+
+    let mut rooms: Vec<Room> = games
+        .iter()
+        .filter_map(|game| {
+            let game = game.lock().unwrap();
+            game.state.config.public.then(|| Room::new(&game))
+        })
+        .collect();
+    rooms.sort_by(|a, b| a.token.cmp(&b.token));
+    rooms
+}
+
+pub async fn list(State(state): State<Arc<AppState>>) -> Json<Vec<Room>> {
+    Json(public_rooms(&state))
+}
+
+pub async fn feed(State(state): State<Arc<AppState>>, ws: WebSocketUpgrade) -> Response {
+    ws.on_upgrade(|socket| send_rooms(socket, state))
+}
+
+/// Sends the room list whenever it changes. Rooms come and go from many
+/// places, so the list is checked once a second rather than announced.
+async fn send_rooms(mut socket: WebSocket, state: Arc<AppState>) {
+    let mut sent: Option<Vec<Room>> = None;
+    loop {
+        let rooms = public_rooms(&state);
+        if sent.as_ref() != Some(&rooms) {
+            let json = serde_json::to_string(&rooms).unwrap();
+            if socket.send(Message::Text(json)).await.is_err() {
+                debug!("Lobby: Connection lost");
+                return;
+            }
+            sent = Some(rooms);
+        }
+
+        tokio::select! {
+            _ = sleep(Duration::from_secs(1)) => {}
+            msg = socket.recv() => match msg {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => {
+                    debug!("Lobby: Client disconnected");
+                    return;
+                }
+                _ => {}
+            }
+        }
+    }
+}
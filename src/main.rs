@@ -1,33 +1,39 @@
 mod game;
+mod lobby;
+mod matchmaking;
+mod profiles;
 mod site;
+mod stats;
+mod store;
 
-use crate::game::Game;
+use crate::game::{Config, Difficulty, EarlyDraw, Game, TimeControl, Variant};
 use axum::{
     extract::{
         ws::{Message, WebSocket, WebSocketUpgrade},
-        Query, State,
+        Path, Query, State,
     },
     http::StatusCode,
     response::{IntoResponse, Response},
     routing::get,
-    Router,
+    Json, Router,
 };
 use rand::{distributions::Alphanumeric, Rng};
-// use redis::aio::ConnectionManager;
 use serde::Deserialize;
 use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, Weak};
 use tokio::sync::watch::Receiver;
 use tokio::time::{sleep, Duration};
 use tower_http::trace::TraceLayer;
-use tracing::debug;
-use tracing_subscriber;
+use tracing::{debug, error};
 
 #[derive(Debug)]
 struct AppState {
-    // pub redis_conn_mgr: ConnectionManager,
     pub games: Arc<Mutex<HashMap<String, Arc<Mutex<Game>>>>>,
+    /// Players waiting to be paired by matchmaking, longest waiting first.
+    pub queue: Arc<Mutex<Vec<matchmaking::Waiting>>>,
+    pub profiles: profiles::Profiles,
+    pub store: store::GameStore,
 }
 
 impl Display for AppState {
@@ -44,20 +50,58 @@ impl Display for AppState {
 async fn main() {
     tracing_subscriber::fmt::init();
 
-    // let redis_address = std::env::var("REDIS_ADDRESS").unwrap();
-    // let client = redis::Client::open(redis_address).unwrap();
-    // let redis_conn_mgr = client.get_tokio_connection_manager().await.unwrap();
+    let database_path =
+        std::env::var("DATABASE_PATH").unwrap_or_else(|_| "tictactoe.sqlite3".to_string());
+    let profiles = profiles::Profiles::open(&database_path).unwrap();
+    let store = store::GameStore::open(&database_path).unwrap();
+    let saved = store.load_all().unwrap();
 
     let shared_state = Arc::new(AppState {
-        // redis_conn_mgr: redis_conn_mgr,
         games: Arc::new(Mutex::new(HashMap::new())),
+        queue: Arc::new(Mutex::new(Vec::new())),
+        profiles,
+        store,
         // sockets: Vec::new(),
     });
 
+    for saved in saved {
+        let (game, receive_from_game) = Game::restore(saved.token, saved.snapshot);
+        if !game.has_humans() {
+            // Nobody could ever come back to it
+            if let Err(e) = shared_state.store.delete(&saved.id) {
+                error!("Store: Could not delete game {}: {}", game.id, e);
+            }
+            continue;
+        }
+        let game = register_game(&shared_state, (game, receive_from_game), Some(saved.id));
+        let players: Vec<game::PlayerID> = {
+            let game = game.lock().unwrap();
+            game.state
+                .players
+                .iter()
+                .filter(|p| p.bot.is_none())
+                .map(|p| p.id)
+                .collect()
+        };
+        for id in players {
+            tokio::spawn(expire_seat(game.clone(), id, shared_state.clone()));
+        }
+    }
+    println!(
+        "Restored {} saved games",
+        shared_state.games.lock().unwrap().len()
+    );
+
     let app = Router::new()
         .route("/", get(site::index))
         .route("/ws", get(open_conn))
+        .route("/games/:token/events", get(game_events))
         .route("/health", get(|| async { StatusCode::OK }))
+        .route("/leaderboard", get(stats::leaderboard))
+        .route("/players/:id", get(stats::player))
+        .route("/lobby", get(lobby::list))
+        .route("/lobby/ws", get(lobby::feed))
+        .route("/match", get(matchmaking::open_conn))
         .fallback(get(site::static_file_server))
         .with_state(shared_state)
         .layer(TraceLayer::new_for_http());
@@ -75,8 +119,61 @@ async fn main() {
 struct NewGameParams {
     #[serde(default)]
     pub token: Option<String>,
+    /// Reclaims a seat in the game `token` after a dropped connection.
+    #[serde(default)]
+    pub secret: Option<String>,
+    /// Takes an open seat in the game `token`. Without it the connection
+    /// only watches.
+    #[serde(default)]
+    pub invite: Option<String>,
+    /// Key of the profile to play under. A new profile is made without it.
+    #[serde(default)]
+    pub profile: Option<String>,
     #[serde(default)]
     pub name: Option<String>,
+    #[serde(default)]
+    pub variant: Option<Variant>,
+    #[serde(default)]
+    pub width: Option<usize>,
+    #[serde(default)]
+    pub height: Option<usize>,
+    #[serde(default)]
+    pub win_length: Option<usize>,
+    #[serde(default)]
+    pub boards: Option<usize>,
+    #[serde(default)]
+    pub misere: Option<bool>,
+    #[serde(default)]
+    pub hints: Option<bool>,
+    #[serde(default)]
+    pub opponent: Option<Opponent>,
+    #[serde(default)]
+    pub difficulty: Option<Difficulty>,
+    #[serde(default)]
+    pub early_draw: Option<EarlyDraw>,
+    /// Games in a match.
+    #[serde(default)]
+    pub best_of: Option<usize>,
+    #[serde(default)]
+    pub spectator_chat: Option<bool>,
+    #[serde(default)]
+    pub public: Option<bool>,
+    /// Seconds allowed per move.
+    #[serde(default)]
+    pub move_time: Option<u64>,
+    /// Seconds each player has for the whole game.
+    #[serde(default)]
+    pub game_time: Option<u64>,
+    /// Seconds added after each move when playing with `game_time`.
+    #[serde(default)]
+    pub increment: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Opponent {
+    Human,
+    Bot,
 }
 
 impl NewGameParams {
@@ -87,11 +184,30 @@ impl NewGameParams {
                 .clone()
                 .map(|s| s.trim().to_string())
                 .filter(|s| !s.is_empty()),
+            secret: self.secret.clone().filter(|s| !s.is_empty()),
+            invite: self.invite.clone().filter(|s| !s.is_empty()),
+            profile: self.profile.clone().filter(|s| !s.is_empty()),
             name: self
                 .name
                 .clone()
                 .map(|s| s.trim().to_string())
                 .filter(|s| !s.is_empty()),
+            variant: self.variant,
+            width: self.width,
+            height: self.height,
+            win_length: self.win_length,
+            boards: self.boards,
+            misere: self.misere,
+            hints: self.hints,
+            opponent: self.opponent,
+            difficulty: self.difficulty,
+            early_draw: self.early_draw,
+            best_of: self.best_of,
+            spectator_chat: self.spectator_chat,
+            public: self.public,
+            move_time: self.move_time,
+            game_time: self.game_time,
+            increment: self.increment,
         }
     }
 
@@ -101,7 +217,50 @@ impl NewGameParams {
                 return false;
             }
         }
-        return true;
+        if self.move_time.is_some() && self.game_time.is_some() {
+            return false;
+        }
+        self.config().validate().is_ok()
+    }
+
+    /// Only used when the connection creates a new game.
+    pub fn config(&self) -> Config {
+        let config = Config::new(
+            self.variant.unwrap_or_default(),
+            self.width,
+            self.height,
+            self.win_length,
+            self.boards,
+            self.misere.unwrap_or(false),
+        );
+        Config {
+            hints: self.hints.unwrap_or(config.hints),
+            time_control: self.time_control(),
+            early_draw: self.early_draw.unwrap_or_default(),
+            best_of: self.best_of,
+            spectator_chat: self.spectator_chat.unwrap_or(config.spectator_chat),
+            public: self.public.unwrap_or(config.public),
+            ..config
+        }
+    }
+
+    fn time_control(&self) -> Option<TimeControl> {
+        match (self.move_time, self.game_time) {
+            (Some(seconds), _) => Some(TimeControl::PerMove { seconds }),
+            (None, Some(seconds)) => Some(TimeControl::Total {
+                seconds,
+                increment: self.increment.unwrap_or(0),
+            }),
+            (None, None) => None,
+        }
+    }
+
+    /// Difficulty of the computer opponent a new game should start with.
+    pub fn bot(&self) -> Option<Difficulty> {
+        match self.opponent {
+            Some(Opponent::Bot) => Some(self.difficulty.unwrap_or(Difficulty::Medium)),
+            _ => None,
+        }
     }
 }
 
@@ -118,90 +277,296 @@ async fn open_conn(
     ws.on_upgrade(|socket| handle_socket(socket, params, state))
 }
 
+/// Everything done to a game so far, for auditing or replaying it.
+async fn game_events(
+    Path(token): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<game::Event>>, StatusCode> {
+    let game = state
+        .games
+        .lock()
+        .unwrap()
+        .get(&token)
+        .cloned()
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let events = game.lock().unwrap().events().to_vec();
+    Ok(Json(events))
+}
+
+/// Who a connection speaks for.
+#[derive(Debug, Clone, Copy)]
+enum Seat {
+    Player(game::PlayerID),
+    Spectator(game::PlayerID),
+}
+
+#[derive(Debug)]
 struct JoinGameResult {
     id: String,
-    player: game::Player,
+    seat: Seat,
+    /// Secret of the seat, empty for spectators.
+    secret: String,
+    invite: String,
+    /// Key of the profile the player plays under, if any.
+    profile: Option<String>,
     game_state: game::State,
     receive_from_game: Receiver<game::State>,
 }
 
-async fn handle_socket(mut socket: WebSocket, params: NewGameParams, state: Arc<AppState>) {
-    // let redis = state.redis_conn_mgr.clone();
+impl JoinGameResult {
+    fn new(game: &Game, seat: Seat) -> JoinGameResult {
+        let secret = match seat {
+            Seat::Player(id) => game.player_secret(id).unwrap_or_default(),
+            Seat::Spectator(_) => String::new(),
+        };
+        JoinGameResult {
+            id: game.id.clone(),
+            seat,
+            secret,
+            invite: game.invite().to_string(),
+            profile: None,
+            game_state: game.state.clone(),
+            receive_from_game: game.state_changes.subscribe(),
+        }
+    }
+}
+
+async fn handle_socket(socket: WebSocket, params: NewGameParams, state: Arc<AppState>) {
     debug!("New WebSocket connection with params: '{:?}'", params);
 
+    let existing = params.token.as_ref().and_then(|token| {
+        let games = state.games.lock().unwrap();
+        games.get(token).cloned()
+    });
+    if let (Some(secret), Some(game)) = (&params.secret, existing) {
+        let reconnected = {
+            let mut game = game.lock().unwrap();
+            game.reconnect(secret).map(|player| {
+                game.broadcast_state();
+                JoinGameResult {
+                    profile: params.profile.clone(),
+                    ..JoinGameResult::new(&game, Seat::Player(player.id))
+                }
+            })
+        };
+        return match reconnected {
+            Ok(joined) => play(socket, game, joined, state).await,
+            Err(e) => send_error(socket, e).await,
+        };
+    }
+
+    let mut created = false;
     let game: Arc<Mutex<Game>> = params
         .token
         .clone()
         .and_then(|token| {
             // if we have a token, try to get the game matching the token
             let games = state.games.lock().unwrap();
-            games.get(&token).map(|g| g.clone())
+            games.get(&token).cloned()
         })
         .unwrap_or_else(|| {
             // if after that we still don't have a game, create a new one
 
-            let id: String = params.token.unwrap_or_else(|| random_token());
+            let id: String = params.token.clone().unwrap_or_else(random_token);
             // TODO: when generating random token, check for collisions
 
-            let (game, _) = Game::new(id.clone());
-
-            let game = Arc::new(Mutex::new(game));
-
-            state.games.lock().unwrap().insert(id, game.clone());
-            game
+            created = true;
+            register_game(&state, Game::new(id, params.config()), None)
         });
 
+    let name = params
+        .name
+        .clone()
+        .unwrap_or_else(|| "Unnamed Player".to_string());
+    let (invited, may_play) = {
+        let game = game.lock().unwrap();
+        let invited = match &params.invite {
+            Some(invite) => invite == game.invite(),
+            None => created || game.state.config.public,
+        };
+        (invited, invited && !game.is_full())
+    };
+    // Only players get a profile, and loading it must not hold up the game
+    let profile = if may_play {
+        load_profile(&state, params.profile.clone(), name.clone()).await
+    } else {
+        None
+    };
+
     // now that we got a game, add the connected user as a player,
     // extract some data from it and send state to client
     let join_game_result: Result<JoinGameResult, String> = {
         let mut game = game.lock().unwrap();
 
-        match game.add_player(params.name.unwrap_or_else(|| "Unnamed Player".to_string())) {
-            Ok(_player) => {
+        let joined = if params.invite.is_some() && !invited {
+            Err("Invalid invite".to_string())
+        } else if !invited || game.is_full() {
+            game.add_spectator(name).map(|s| Seat::Spectator(s.id))
+        } else {
+            game.add_player(name).and_then(|player| {
+                if let (true, Some(difficulty)) = (created, params.bot()) {
+                    game.add_bot(difficulty)?;
+                }
+                Ok(Seat::Player(player.id))
+            })
+        };
+
+        match joined {
+            Ok(seat) => {
+                let profile = match seat {
+                    Seat::Player(id) => link_profile(&mut game, id, profile),
+                    Seat::Spectator(_) => None,
+                };
                 game.broadcast_state();
 
                 Ok(JoinGameResult {
-                    id: game.id.clone(),
-                    player: _player,
-                    game_state: game.state.clone(),
-                    receive_from_game: game.state_changes.subscribe(),
+                    profile,
+                    ..JoinGameResult::new(&game, seat)
                 })
             }
             Err(e) => Err(e),
         }
     };
 
-    let join_game_result = match join_game_result {
-        Ok(j) => j,
+    match join_game_result {
+        Ok(joined) => play(socket, game, joined, state).await,
+        Err(e) => send_error(socket, e).await,
+    }
+}
+
+/// Adds a game to the room list and starts the tasks that run it. A
+/// restored game keeps the key it was `saved_as`, new ones get their own.
+fn register_game(
+    state: &Arc<AppState>,
+    (game, receive_from_game): (Game, Receiver<game::State>),
+    saved_as: Option<String>,
+) -> Arc<Mutex<Game>> {
+    let id = game.id.clone();
+    let save_id = saved_as.unwrap_or_else(game::new_secret);
+    let game = Arc::new(Mutex::new(game));
+    tokio::spawn(run_timers(Arc::downgrade(&game), receive_from_game.clone()));
+    tokio::spawn(run_bots(Arc::downgrade(&game), receive_from_game.clone()));
+    tokio::spawn(record_results(
+        Arc::downgrade(&game),
+        receive_from_game.clone(),
+        state.clone(),
+    ));
+    tokio::spawn(save_game(
+        Arc::downgrade(&game),
+        id.clone(),
+        save_id,
+        receive_from_game,
+        state.clone(),
+    ));
+
+    state.games.lock().unwrap().insert(id, game.clone());
+    game
+}
+
+/// Takes `game` off the room list, unless its token `id` has since gone to
+/// another room.
+fn unregister_game(state: &AppState, game: &Arc<Mutex<Game>>, id: &str) {
+    let mut games = state.games.lock().unwrap();
+    if games.get(id).is_some_and(|g| Arc::ptr_eq(g, game)) {
+        games.remove(id);
+    }
+}
+
+/// Finds the profile for `key` and renames it to `name`, or creates one.
+/// SQLite blocks, so this runs on a blocking thread. None if the database
+/// failed, in which case the player simply goes unrated.
+async fn load_profile(
+    state: &Arc<AppState>,
+    key: Option<String>,
+    name: String,
+) -> Option<profiles::Profile> {
+    let state = state.clone();
+    let loaded =
+        tokio::task::spawn_blocking(move || state.profiles.find_or_create(key.as_deref(), &name))
+            .await
+            .map_err(|e| e.to_string())
+            .and_then(|loaded| loaded);
+    match loaded {
+        Ok(profile) => Some(profile),
         Err(e) => {
-            let json = serde_json::to_string(&game::ToBrowser::Error(e)).unwrap();
-            socket.send(Message::Text(json)).await.unwrap();
-            socket.close().await.unwrap();
-            return;
+            error!("Profiles: Could not load profile: {}", e);
+            None
         }
-    };
+    }
+}
+
+/// Rates player `id`'s games under `profile`. Returns the key to hand back
+/// to them.
+fn link_profile(
+    game: &mut Game,
+    id: game::PlayerID,
+    profile: Option<profiles::Profile>,
+) -> Option<String> {
+    let profile = profile?;
+    game.link_profile(id, profile.id, profile.display_rating());
+    Some(profile.key)
+}
 
-    let player = join_game_result.player;
+async fn send_error(mut socket: WebSocket, e: String) {
+    let json = serde_json::to_string(&game::ToBrowser::Error(e)).unwrap();
+    let _ = socket.send(Message::Text(json)).await;
+    let _ = socket.close().await;
+}
+
+/// Relays messages between a connection and its game until it closes.
+async fn play(
+    mut socket: WebSocket,
+    game: Arc<Mutex<Game>>,
+    join_game_result: JoinGameResult,
+    state: Arc<AppState>,
+) {
+    let seat = join_game_result.seat;
+    let secret = join_game_result.secret;
     let mut receive_from_game = join_game_result.receive_from_game;
 
-    let json = serde_json::to_string(&game::ToBrowser::JoinedGame {
-        token: join_game_result.id,
-        player_id: player.id,
-        state: join_game_result.game_state,
-    })
-    .unwrap();
-    socket.send(Message::Text(json)).await.unwrap();
-
-    let disconnect = || {
-        debug!(
-            "Socket: Player {:?} disconnected, removing from game",
-            player
-        );
+    let joined = match seat {
+        Seat::Player(player_id) => game::ToBrowser::JoinedGame {
+            token: join_game_result.id,
+            player_id,
+            secret: secret.clone(),
+            invite: join_game_result.invite,
+            profile: join_game_result.profile,
+            state: join_game_result.game_state,
+        },
+        Seat::Spectator(spectator_id) => game::ToBrowser::Spectating {
+            token: join_game_result.id,
+            spectator_id,
+            state: join_game_result.game_state,
+        },
+    };
+    let json = serde_json::to_string(&joined).unwrap();
+    if socket.send(Message::Text(json)).await.is_err() {
+        debug!("Socket: Connection lost before joining");
+    }
+
+    // A player whose connection dropped, rather than one who left, keeps
+    // their seat for a while in case they come back
+    let disconnect = |dropped: bool| {
+        debug!("Socket: {:?} disconnected", seat);
+        let room = game.clone();
         let mut game = game.lock().unwrap();
-        game.remove_player(player.id);
-        if game.state.players.is_empty() {
+        if let Seat::Player(id) = seat {
+            if game.authorize(id, &secret).is_err() {
+                debug!("Socket: Seat was taken over by another connection");
+                return;
+            }
+            if dropped {
+                tokio::spawn(expire_seat(room.clone(), id, state.clone()));
+            }
+        }
+        match seat {
+            Seat::Player(id) if dropped => game.disconnect_player(id),
+            Seat::Player(id) => game.remove_player(id),
+            Seat::Spectator(id) => game.remove_spectator(id),
+        }
+        if !game.has_humans() {
             debug!("Socket: Game is empty, removing globally");
-            state.games.lock().unwrap().remove(&game.id);
+            unregister_game(&state, &room, &game.id);
         }
         game.broadcast_state();
     };
@@ -210,14 +575,22 @@ async fn handle_socket(mut socket: WebSocket, params: NewGameParams, state: Arc<
         tokio::select! {
             _ = sleep(Duration::from_secs(10)) => {
                 debug!("Socket: Ping");
-                socket.send(Message::Ping(vec![])).await.unwrap();
+                if socket.send(Message::Ping(vec![])).await.is_err() {
+                    debug!("Socket: Connection lost");
+                    disconnect(true);
+                    return;
+                }
             }
             _ = receive_from_game.changed() => {
                 let new_state = receive_from_game.borrow().clone();
                 // trace!("Socket: Sending game state change: {:?}", new_state);
 
                 let json = serde_json::to_string(&game::ToBrowser::GameState(new_state)).unwrap();
-                socket.send(Message::Text(json)).await.unwrap();
+                if socket.send(Message::Text(json)).await.is_err() {
+                    debug!("Socket: Connection lost");
+                    disconnect(true);
+                    return;
+                }
             }
             msg = socket.recv() => {
                 match msg {
@@ -225,43 +598,69 @@ async fn handle_socket(mut socket: WebSocket, params: NewGameParams, state: Arc<
                         debug!("Socket: Received message: {:?}", raw_msg);
                         match raw_msg {
                             Ok(Message::Text(json)) => {
-                                let parsed: game::FromBrowser = serde_json::from_str(&json).unwrap();
+                                let parsed = serde_json::from_str::<game::FromBrowser>(&json)
+                                    .map_err(|e| format!("Invalid message: {}", e));
                                 debug!("Socket: Parsed message: {:?}", parsed);
 
-                                let server_err = {
+                                let result = parsed.and_then(|parsed| {
                                     let mut game = game.lock().unwrap();
-                                    let result = game.handle_msg(player.id, parsed);
-                                    match result {
-                                        Ok(changed) => {
-                                            if changed {
-                                                game.broadcast_state();
-                                            }
-                                            None
-                                        }
-
-                                        Err(e) => {
-                                            Some(e)
+                                    let result = match seat {
+                                        Seat::Player(id) => game
+                                            .authorize(id, &secret)
+                                            .and_then(|_| game.handle_msg(id, parsed)),
+                                        Seat::Spectator(id) => {
+                                            game.handle_spectator_msg(id, parsed)
                                         }
+                                    };
+                                    if let Ok(game::Response::Changed) = result {
+                                        game.broadcast_state();
                                     }
+                                    result
                                     // lock game
+                                });
+                                let reply = match result {
+                                    Ok(game::Response::Changed) => None,
+
+                                    Ok(game::Response::Hint(request)) => {
+                                        let hint =
+                                            tokio::task::spawn_blocking(move || request.analyze())
+                                                .await
+                                                .unwrap_or_else(|e| Err(e.to_string()));
+                                        Some(match hint {
+                                            Ok(hint) => game::ToBrowser::Hint(hint),
+                                            Err(e) => game::ToBrowser::Error(e),
+                                        })
+                                    }
+
+                                    Err(e) => {
+                                        debug!("Socket: Error handling message: {:?}", e);
+                                        Some(game::ToBrowser::Error(e))
+                                    }
                                 };
 
-                                if let Some(e) = server_err {
-                                    debug!("Socket: Error handling message: {:?}", e);
-                                    let json = serde_json::to_string(&game::ToBrowser::Error(e)).unwrap();
-                                    socket.send(Message::Text(json)).await.unwrap();
+                                if let Some(reply) = reply {
+                                    let json = serde_json::to_string(&reply).unwrap();
+                                    if socket.send(Message::Text(json)).await.is_err() {
+                                        debug!("Socket: Connection lost");
+                                        disconnect(true);
+                                        return;
+                                    }
                                 }
                             }
 
                             Ok(Message::Close(_)) => {
                                 debug!("Socket: Client closed connection");
-                                disconnect();
+                                disconnect(false);
                                 return;
                             }
 
                             Ok(Message::Ping(_)) => {
                                 debug!("Socket: Client pinged");
-                                socket.send(Message::Pong(vec![])).await.unwrap();
+                                if socket.send(Message::Pong(vec![])).await.is_err() {
+                                    debug!("Socket: Connection lost");
+                                    disconnect(true);
+                                    return;
+                                }
                             }
 
                             Ok(Message::Pong(_)) => {
@@ -275,7 +674,7 @@ async fn handle_socket(mut socket: WebSocket, params: NewGameParams, state: Arc<
                     }
                     None => {
                         debug!("Socket: Client disconnected");
-                        disconnect();
+                        disconnect(true);
                         return;
                     }
                 }
@@ -284,10 +683,174 @@ async fn handle_socket(mut socket: WebSocket, params: NewGameParams, state: Arc<
     }
 }
 
+/// Gives up a seat held for a dropped player once they have had time to come
+/// back, and drops the game if that leaves nobody.
+async fn expire_seat(room: Arc<Mutex<Game>>, player_id: game::PlayerID, state: Arc<AppState>) {
+    sleep(Duration::from_millis(game::RECONNECT_GRACE_MILLIS)).await;
+    let mut game = room.lock().unwrap();
+    if !game.expire_seat(player_id) {
+        return;
+    }
+    debug!("Socket: Player {} did not come back", player_id);
+    if !game.has_humans() {
+        debug!("Socket: Game is empty, removing globally");
+        unregister_game(&state, &room, &game.id);
+    }
+    game.broadcast_state();
+}
+
+/// Ends the game when the player to move runs out of time, and drops rematch
+/// offers nobody answered, even if nobody sends anything. Stops once the game
+/// is gone.
+async fn run_timers(game: Weak<Mutex<Game>>, mut receive_from_game: Receiver<game::State>) {
+    loop {
+        let timeout = match game.upgrade() {
+            Some(game) => {
+                let mut game = game.lock().unwrap();
+                if game.pass_time() {
+                    debug!("Timers: Time ran out in game {}", game.id);
+                    game.broadcast_state();
+                }
+                game.next_timeout()
+            }
+            None => return,
+        };
+
+        // Check again when time runs out, or sooner if the game changes
+        let changed = match timeout {
+            Some(timeout) => tokio::select! {
+                _ = sleep(timeout) => Ok(()),
+                changed = receive_from_game.changed() => changed,
+            },
+            None => receive_from_game.changed().await,
+        };
+        if changed.is_err() {
+            return;
+        }
+    }
+}
+
+/// Moves for computer players whenever it is their turn. They think on a
+/// blocking thread, so the game stays free to play meanwhile. Stops once the
+/// game is gone.
+async fn run_bots(game: Weak<Mutex<Game>>, mut receive_from_game: Receiver<game::State>) {
+    loop {
+        let turn = match game.upgrade() {
+            Some(game) => game.lock().unwrap().bot_turn(),
+            None => return,
+        };
+        if let Some(turn) = turn {
+            let thinking = turn.clone();
+            let thought = tokio::task::spawn_blocking(move || thinking.think()).await;
+            if let (Ok(Some(msg)), Some(game)) = (thought, game.upgrade()) {
+                let mut game = game.lock().unwrap();
+                match game.play_bot(&turn, msg) {
+                    Ok(()) => {
+                        game.broadcast_state();
+                        continue;
+                    }
+                    Err(e) => debug!("Bots: Player {} could not play: {}", turn.player_id, e),
+                }
+            }
+        }
+
+        if receive_from_game.changed().await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Records games as they finish and updates the players' ratings. Stops once
+/// the game is gone.
+async fn record_results(
+    game: Weak<Mutex<Game>>,
+    mut receive_from_game: Receiver<game::State>,
+    state: Arc<AppState>,
+) {
+    while receive_from_game.changed().await.is_ok() {
+        let results = match game.upgrade() {
+            Some(game) => game.lock().unwrap().take_results(),
+            None => return,
+        };
+        for result in results {
+            let recorder = state.clone();
+            let recorded = tokio::task::spawn_blocking(move || recorder.profiles.record(&result))
+                .await
+                .map_err(|e| e.to_string())
+                .and_then(|recorded| recorded);
+            let ratings = match recorded {
+                Ok(ratings) => ratings,
+                Err(e) => {
+                    error!("Profiles: Could not record game: {}", e);
+                    continue;
+                }
+            };
+            if ratings.is_empty() {
+                continue;
+            }
+            if let Some(game) = game.upgrade() {
+                let mut game = game.lock().unwrap();
+                for (profile, rating) in ratings {
+                    game.update_rating(profile, rating);
+                }
+                game.broadcast_state();
+            }
+        }
+    }
+}
+
+/// Saves the game under `save_id` whenever it changes, so that it survives a
+/// restart, and deletes it once everyone has left. SQLite blocks, so the
+/// writes run on a blocking thread, one at a time and in order.
+async fn save_game(
+    game: Weak<Mutex<Game>>,
+    id: String,
+    save_id: String,
+    mut receive_from_game: Receiver<game::State>,
+    state: Arc<AppState>,
+) {
+    // Events saved so far. A restored game saves its whole log once, which
+    // also moves games saved in the older format to the event table.
+    let mut saved = 0;
+    loop {
+        let (snapshot, events) = match game.upgrade() {
+            Some(game) => {
+                let game = game.lock().unwrap();
+                (game.snapshot(saved), game.events().len())
+            }
+            None => break,
+        };
+        let (store, token, key) = (state.clone(), id.clone(), save_id.clone());
+        let since = saved;
+        let written =
+            tokio::task::spawn_blocking(move || store.store.save(&key, &token, snapshot, since))
+                .await
+                .map_err(|e| e.to_string())
+                .and_then(|written| written);
+        match written {
+            Ok(()) => saved = events,
+            Err(e) => error!("Store: Could not save game {}: {}", id, e),
+        }
+        if receive_from_game.changed().await.is_err() {
+            break;
+        }
+    }
+
+    debug!("Store: Game {} is over, deleting it", id);
+    let store = state.clone();
+    let deleted = tokio::task::spawn_blocking(move || store.store.delete(&save_id))
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|deleted| deleted);
+    if let Err(e) = deleted {
+        error!("Store: Could not delete game {}: {}", id, e);
+    }
+}
+
 fn random_token() -> String {
-    return rand::thread_rng()
+    rand::thread_rng()
         .sample_iter(&Alphanumeric)
         .take(7)
         .map(char::from)
-        .collect();
+        .collect()
 }
@@ -0,0 +1,235 @@
+use crate::game::{Config, Difficulty, Game, Variant};
+use crate::profiles::Profile;
+use crate::{
+    link_profile, load_profile, play, random_token, register_game, unregister_game, AppState,
+    JoinGameResult, Seat,
+};
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Query, State,
+    },
+    response::Response,
+};
+use serde::Deserialize;
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+use tokio::time::{sleep, Duration};
+use tracing::debug;
+
+/// Seconds to wait for another player before playing the computer instead.
+const DEFAULT_WAIT: u64 = 30;
+const MAX_WAIT: u64 = 300;
+
+#[derive(Debug, Deserialize)]
+pub struct MatchParams {
+    /// Key of the profile to play under, as for `/ws`.
+    #[serde(default)]
+    pub profile: Option<String>,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub variant: Option<Variant>,
+    /// Seconds to wait for an opponent before getting a computer player.
+    #[serde(default)]
+    pub wait: Option<u64>,
+}
+
+/// A connection waiting for an opponent.
+#[derive(Debug)]
+pub struct Waiting {
+    /// Tells the connection's own entry apart from the others.
+    ticket: u64,
+    variant: Variant,
+    name: String,
+    profile: Option<Profile>,
+    /// Hands over the seat once someone else has been paired with it.
+    paired: oneshot::Sender<(Arc<Mutex<Game>>, JoinGameResult)>,
+}
+
+pub async fn open_conn(
+    Query(params): Query<MatchParams>,
+    State(state): State<Arc<AppState>>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    ws.on_upgrade(|socket| find_match(socket, params, state))
+}
+
+/// Seats the connection opposite the longest waiting player that wants the
+/// same variant, or queues it until one comes along.
+async fn find_match(mut socket: WebSocket, params: MatchParams, state: Arc<AppState>) {
+    debug!("Match: New connection with params: '{:?}'", params);
+    let variant = params.variant.unwrap_or_default();
+    let name = params
+        .name
+        .map(|s| s.trim().chars().take(32).collect::<String>())
+        .filter(|s| !s.is_empty())
+        .unwrap_or_else(|| "Unnamed Player".to_string());
+    let key = params.profile.filter(|s| !s.is_empty());
+    let profile = load_profile(&state, key, name.clone()).await;
+    let wait = params.wait.unwrap_or(DEFAULT_WAIT).min(MAX_WAIT);
+
+    while let Some(opponent) = take_opponent(&state, variant) {
+        let game = new_game(&state, variant);
+        let (theirs, ours) = {
+            // A new game always has both seats free
+            let mut game = game.lock().unwrap();
+            let theirs = game.add_player(opponent.name).unwrap().id;
+            let ours = game.add_player(name.clone()).unwrap().id;
+            let their_profile = link_profile(&mut game, theirs, opponent.profile);
+            let our_profile = link_profile(&mut game, ours, profile.clone());
+            game.broadcast_state();
+            (
+                JoinGameResult {
+                    profile: their_profile,
+                    ..JoinGameResult::new(&game, Seat::Player(theirs))
+                },
+                JoinGameResult {
+                    profile: our_profile,
+                    ..JoinGameResult::new(&game, Seat::Player(ours))
+                },
+            )
+        };
+
+        if opponent.paired.send((game.clone(), theirs)).is_ok() {
+            return play(socket, game, ours, state).await;
+        }
+        // They left just as we found them. Nobody else has the game, so
+        // dropping it ends it, and the next in line may still be there.
+        let id = game.lock().unwrap().id.clone();
+        debug!("Match: Opponent left before game {} started", id);
+        unregister_game(&state, &game, &id);
+    }
+
+    let ticket = rand::random();
+    let (paired, mut on_paired) = oneshot::channel();
+    state.queue.lock().unwrap().push(Waiting {
+        ticket,
+        variant,
+        name: name.clone(),
+        profile: profile.clone(),
+        paired,
+    });
+    debug!("Match: {} is waiting for a {:?} game", name, variant);
+
+    let timeout = sleep(Duration::from_secs(wait));
+    tokio::pin!(timeout);
+    loop {
+        tokio::select! {
+            result = &mut on_paired => {
+                if let Ok((game, joined)) = result {
+                    play(socket, game, joined, state).await;
+                }
+                return;
+            }
+            _ = &mut timeout => {
+                // Nobody came, play the computer instead. If we are no longer
+                // in the queue someone is pairing with us, so wait for them.
+                let queued = {
+                    let mut queue = state.queue.lock().unwrap();
+                    let position = queue.iter().position(|w| w.ticket == ticket);
+                    position.map(|i| queue.remove(i)).is_some()
+                };
+                if !queued {
+                    if let Ok((game, joined)) = (&mut on_paired).await {
+                        return play(socket, game, joined, state).await;
+                    }
+                }
+                debug!("Match: Nobody for {}, starting a game against a bot", name);
+
+                let game = new_game(&state, variant);
+                let joined = {
+                    let mut game = game.lock().unwrap();
+                    let player = game.add_player(name).unwrap().id;
+                    game.add_bot(Difficulty::Medium).unwrap();
+                    let profile = link_profile(&mut game, player, profile);
+                    game.broadcast_state();
+                    JoinGameResult {
+                        profile,
+                        ..JoinGameResult::new(&game, Seat::Player(player))
+                    }
+                };
+                return play(socket, game, joined, state).await;
+            }
+            msg = socket.recv() => match msg {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => {
+                    // Dropping the receiver takes us out of the queue
+                    debug!("Match: {} stopped waiting", name);
+                    return;
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Takes the longest waiting connection that wants `variant` out of the
+/// queue, skipping any that already went away.
+fn take_opponent(state: &AppState, variant: Variant) -> Option<Waiting> {
+    let mut queue = state.queue.lock().unwrap();
+    queue.retain(|w| !w.paired.is_closed());
+    let position = queue.iter().position(|w| w.variant == variant)?;
+    Some(queue.remove(position))
+}
+
+/// A game with the variant's default settings, registered so that players
+/// can reconnect to it by token. Matched games are rated, so they have no
+/// hints.
+fn new_game(state: &Arc<AppState>, variant: Variant) -> Arc<Mutex<Game>> {
+    let config = Config {
+        hints: false,
+        ..Config::new(variant, None, None, None, None, false)
+    };
+    register_game(state, Game::new(random_token(), config), None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{profiles::Profiles, store::GameStore};
+    use std::collections::HashMap;
+
+    type Paired = oneshot::Receiver<(Arc<Mutex<Game>>, JoinGameResult)>;
+
+    fn waiting(ticket: u64, variant: Variant) -> (Waiting, Paired) {
+        let (paired, on_paired) = oneshot::channel();
+        let waiting = Waiting {
+            ticket,
+            variant,
+            name: ticket.to_string(),
+            profile: None,
+            paired,
+        };
+        (waiting, on_paired)
+    }
+
+    #[test]
+    fn pairs_with_the_longest_waiting_player_for_the_variant() {
+        let state = AppState {
+            games: Arc::new(Mutex::new(HashMap::new())),
+            queue: Arc::new(Mutex::new(Vec::new())),
+            profiles: Profiles::open(":memory:").unwrap(),
+            store: GameStore::open(":memory:").unwrap(),
+        };
+        let mut receivers = Vec::new();
+        for (ticket, variant) in [
+            (1, Variant::Classic),
+            (2, Variant::Gravity),
+            (3, Variant::Classic),
+            (4, Variant::Classic),
+        ] {
+            let (waiting, on_paired) = waiting(ticket, variant);
+            state.queue.lock().unwrap().push(waiting);
+            receivers.push(on_paired);
+        }
+        // The first in line gave up waiting
+        receivers.remove(0);
+
+        let ticket = |w: Option<Waiting>| w.map(|w| w.ticket);
+        assert_eq!(ticket(take_opponent(&state, Variant::Classic)), Some(3));
+        assert_eq!(ticket(take_opponent(&state, Variant::Qubic)), None);
+        assert_eq!(ticket(take_opponent(&state, Variant::Gravity)), Some(2));
+        let queue = state.queue.lock().unwrap();
+        assert_eq!(queue.iter().map(|w| w.ticket).collect::<Vec<_>>(), [4]);
+    }
+}
@@ -0,0 +1,314 @@
+use crate::game::{new_secret, now_secs, GameResult, ProfileID};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Rating every new profile starts at.
+const INITIAL_RATING: f64 = 1200.0;
+/// Most a rating can move after one game.
+const K_FACTOR: f64 = 32.0;
+
+/// Players who keep their identity from game to game, stored in SQLite.
+#[derive(Debug)]
+pub struct Profiles {
+    conn: Mutex<Connection>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Profile {
+    pub id: ProfileID,
+    /// Proves a connection speaks for the profile, only ever sent to its
+    /// owner.
+    pub key: String,
+    pub rating: f64,
+}
+
+/// A player's place on the leaderboard.
+#[derive(Debug, Clone, Serialize)]
+pub struct Ranking {
+    pub id: ProfileID,
+    pub name: String,
+    pub rating: i32,
+    pub rated_games: u32,
+}
+
+/// A recorded game from the point of view of one of its players.
+#[derive(Debug, Clone)]
+pub struct PlayedGame {
+    pub variant: String,
+    /// Dimensions of the board, None for games recorded before they were
+    /// kept.
+    pub board: Option<String>,
+    pub team: char,
+    /// None for a draw.
+    pub winner: Option<char>,
+    /// Team and space of the first move.
+    pub opening: Option<(char, usize)>,
+    pub moves: usize,
+}
+
+impl Profile {
+    /// Rating as shown to players.
+    pub fn display_rating(&self) -> i32 {
+        self.rating.round() as i32
+    }
+}
+
+impl Profiles {
+    /// Opens the database at `path`, creating it if needed.
+    pub fn open(path: &str) -> Result<Profiles, String> {
+        let conn = Connection::open(path).map_err(|e| e.to_string())?;
+        conn.busy_timeout(Duration::from_secs(5))
+            .map_err(|e| e.to_string())?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS profiles (
+                id INTEGER PRIMARY KEY,
+                key TEXT NOT NULL UNIQUE,
+                name TEXT NOT NULL,
+                rating REAL NOT NULL,
+                rated_games INTEGER NOT NULL DEFAULT 0,
+                created_at INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS results (
+                id INTEGER PRIMARY KEY,
+                x INTEGER REFERENCES profiles (id),
+                o INTEGER REFERENCES profiles (id),
+                winner TEXT,
+                variant TEXT NOT NULL,
+                board TEXT,
+                opening_team TEXT,
+                opening_space INTEGER,
+                moves INTEGER NOT NULL,
+                rated INTEGER NOT NULL,
+                finished_at INTEGER NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS results_x ON results (x);
+            CREATE INDEX IF NOT EXISTS results_o ON results (o);",
+        )
+        .map_err(|e| e.to_string())?;
+        // Databases made before results kept the board size lack the column
+        if conn.prepare("SELECT board FROM results LIMIT 0").is_err() {
+            conn.execute("ALTER TABLE results ADD COLUMN board TEXT", [])
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(Profiles {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    /// Finds the profile for `key` and renames it to `name`, or creates a new
+    /// one if there is no key or nobody has it.
+    pub fn find_or_create(&self, key: Option<&str>, name: &str) -> Result<Profile, String> {
+        let conn = self.conn.lock().unwrap();
+        let existing = match key {
+            Some(key) => conn
+                .query_row(
+                    "UPDATE profiles SET name = ?1 WHERE key = ?2 RETURNING id, key, rating",
+                    params![name, key],
+                    |row| {
+                        Ok(Profile {
+                            id: row.get(0)?,
+                            key: row.get(1)?,
+                            rating: row.get(2)?,
+                        })
+                    },
+                )
+                .optional()
+                .map_err(|e| e.to_string())?,
+            None => None,
+        };
+        if let Some(profile) = existing {
+            return Ok(profile);
+        }
+
+        let key = new_secret();
+        conn.execute(
+            "INSERT INTO profiles (key, name, rating, created_at) VALUES (?1, ?2, ?3, ?4)",
+            params![key, name, INITIAL_RATING, now_secs()],
+        )
+        .map_err(|e| e.to_string())?;
+        Ok(Profile {
+            id: conn.last_insert_rowid(),
+            key,
+            rating: INITIAL_RATING,
+        })
+    }
+
+    /// Stores `result` and, if it is rated, updates both players' ratings.
+    /// Returns the new ratings as shown to players.
+    pub fn record(&self, result: &GameResult) -> Result<Vec<(ProfileID, i32)>, String> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+        tx.execute(
+            "INSERT INTO results (x, o, winner, variant, board, opening_team, opening_space, moves, rated, finished_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            params![
+                result.x,
+                result.o,
+                result.winner.map(String::from),
+                format!("{:?}", result.variant),
+                result.board,
+                result.opening.map(|(team, _)| String::from(team)),
+                result.opening.map(|(_, space)| space),
+                result.moves,
+                result.rated(),
+                now_secs(),
+            ],
+        )
+        .map_err(|e| e.to_string())?;
+        let (x, o) = match (result.x, result.o) {
+            (Some(x), Some(o)) if result.rated() => (x, o),
+            _ => {
+                tx.commit().map_err(|e| e.to_string())?;
+                return Ok(Vec::new());
+            }
+        };
+
+        let rating = |id: ProfileID| -> Result<f64, String> {
+            tx.query_row(
+                "SELECT rating FROM profiles WHERE id = ?1",
+                params![id],
+                |row| row.get(0),
+            )
+            .map_err(|e| e.to_string())
+        };
+        let (x_rating, o_rating) = (rating(x)?, rating(o)?);
+
+        let score = match result.winner {
+            Some('X') => 1.0,
+            Some(_) => 0.0,
+            None => 0.5,
+        };
+        let change = K_FACTOR * (score - expected_score(x_rating, o_rating));
+        let ratings = [(x, x_rating + change), (o, o_rating - change)];
+        for (id, rating) in ratings {
+            tx.execute(
+                "UPDATE profiles SET rating = ?1, rated_games = rated_games + 1 WHERE id = ?2",
+                params![rating, id],
+            )
+            .map_err(|e| e.to_string())?;
+        }
+        tx.commit().map_err(|e| e.to_string())?;
+
+        Ok(ratings
+            .iter()
+            .map(|&(id, rating)| (id, rating.round() as i32))
+            .collect())
+    }
+
+    /// The `limit` best rated players who have played a rated game.
+    pub fn leaderboard(&self, limit: usize) -> Result<Vec<Ranking>, String> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn
+            .prepare(
+                "SELECT id, name, rating, rated_games FROM profiles
+                WHERE rated_games > 0 ORDER BY rating DESC, id LIMIT ?1",
+            )
+            .map_err(|e| e.to_string())?;
+        let rankings = stmt
+            .query_map(params![limit], read_ranking)
+            .map_err(|e| e.to_string())?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+        Ok(rankings)
+    }
+
+    /// Name and rating of profile `id`, if there is one.
+    pub fn ranking(&self, id: ProfileID) -> Result<Option<Ranking>, String> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT id, name, rating, rated_games FROM profiles WHERE id = ?1",
+            params![id],
+            read_ranking,
+        )
+        .optional()
+        .map_err(|e| e.to_string())
+    }
+
+    /// Every recorded game profile `id` played, oldest first.
+    pub fn games(&self, id: ProfileID) -> Result<Vec<PlayedGame>, String> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn
+            .prepare(
+                "SELECT x = ?1, winner, opening_team, opening_space, moves, variant, board
+                FROM results WHERE x = ?1 OR o = ?1 ORDER BY id",
+            )
+            .map_err(|e| e.to_string())?;
+        let games = stmt
+            .query_map(params![id], |row| {
+                let team = |s: Option<String>| s.and_then(|s| s.chars().next());
+                let opening_team = team(row.get(2)?);
+                let opening_space: Option<usize> = row.get(3)?;
+                Ok(PlayedGame {
+                    variant: row.get(5)?,
+                    board: row.get(6)?,
+                    team: if row.get(0)? { 'X' } else { 'O' },
+                    winner: team(row.get(1)?),
+                    opening: opening_team.zip(opening_space),
+                    moves: row.get(4)?,
+                })
+            })
+            .map_err(|e| e.to_string())?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+        Ok(games)
+    }
+}
+
+fn read_ranking(row: &rusqlite::Row) -> rusqlite::Result<Ranking> {
+    Ok(Ranking {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        rating: row.get::<_, f64>(2)?.round() as i32,
+        rated_games: row.get(3)?,
+    })
+}
+
+/// Chance a player rated `a` beats one rated `b`, counting a draw as half a
+/// win, per the Elo system.
+fn expected_score(a: f64, b: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf((b - a) / 400.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::Variant;
+
+    #[test]
+    fn even_players_expect_half_a_win() {
+        assert_eq!(expected_score(1200.0, 1200.0), 0.5);
+        let stronger = expected_score(1600.0, 1200.0);
+        assert!((stronger - 10.0 / 11.0).abs() < 1e-9);
+        assert!((stronger + expected_score(1200.0, 1600.0) - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn a_win_between_new_players_moves_ratings_by_half_the_k_factor() {
+        let profiles = Profiles::open(":memory:").unwrap();
+        let x = profiles.find_or_create(None, "Ann").unwrap();
+        let o = profiles.find_or_create(None, "Bob").unwrap();
+        let again = profiles.find_or_create(Some(&x.key), "Annie").unwrap();
+        assert_eq!(again.id, x.id);
+
+        let result = GameResult {
+            x: Some(x.id),
+            o: Some(o.id),
+            winner: Some('X'),
+            variant: Variant::Classic,
+            board: "3x3".to_string(),
+            opening: Some(('X', 4)),
+            moves: 5,
+        };
+        let ratings = profiles.record(&result).unwrap();
+        assert_eq!(ratings, vec![(x.id, 1216), (o.id, 1184)]);
+
+        let ranking = profiles.ranking(x.id).unwrap().unwrap();
+        assert_eq!((ranking.name.as_str(), ranking.rated_games), ("Annie", 1));
+        let games = profiles.games(o.id).unwrap();
+        assert_eq!(games.len(), 1);
+        assert_eq!((games[0].team, games[0].winner), ('O', Some('X')));
+        assert_eq!(games[0].board.as_deref(), Some("3x3"));
+    }
+}
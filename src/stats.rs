@@ -0,0 +1,216 @@
+use crate::game::ProfileID;
+use crate::profiles::{PlayedGame, Profiles, Ranking};
+use crate::AppState;
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::error;
+
+const DEFAULT_LEADERBOARD_SIZE: usize = 20;
+const MAX_LEADERBOARD_SIZE: usize = 100;
+
+#[derive(Debug, Deserialize)]
+pub struct LeaderboardParams {
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+/// How a game went for one player.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Finish {
+    Win,
+    Loss,
+    Draw,
+}
+
+impl Finish {
+    fn of(game: &PlayedGame) -> Finish {
+        match game.winner {
+            Some(team) if team == game.team => Finish::Win,
+            Some(_) => Finish::Loss,
+            None => Finish::Draw,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Record {
+    pub games: usize,
+    pub wins: usize,
+    pub losses: usize,
+    pub draws: usize,
+    /// Share of games won, from 0 to 1.
+    pub win_rate: f64,
+}
+
+impl Record {
+    fn add(&mut self, finish: Finish) {
+        self.games += 1;
+        match finish {
+            Finish::Win => self.wins += 1,
+            Finish::Loss => self.losses += 1,
+            Finish::Draw => self.draws += 1,
+        }
+        self.win_rate = self.wins as f64 / self.games as f64;
+    }
+}
+
+/// The first move a player most often makes in one variant on one size of
+/// board.
+#[derive(Debug, Clone, Serialize)]
+pub struct Opening {
+    pub variant: String,
+    pub board: String,
+    pub space: usize,
+    /// Games the player opened with it.
+    pub games: usize,
+}
+
+/// Games in a row that ended the same way.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Streak {
+    pub finish: Finish,
+    pub games: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PlayerStats {
+    #[serde(flatten)]
+    pub ranking: Ranking,
+    /// Every recorded game, rated or not.
+    pub overall: Record,
+    pub as_x: Record,
+    pub as_o: Record,
+    /// How the most recent games went, None before the first game.
+    pub current_streak: Option<Streak>,
+    pub longest_win_streak: usize,
+    /// Space the player most often makes the first move in, for each
+    /// variant and board size they opened a game in, most played first.
+    pub favorite_openings: Vec<Opening>,
+    /// Moves per game.
+    pub average_length: Option<f64>,
+}
+
+impl PlayerStats {
+    fn new(ranking: Ranking, games: &[PlayedGame]) -> PlayerStats {
+        let mut stats = PlayerStats {
+            ranking,
+            overall: Record::default(),
+            as_x: Record::default(),
+            as_o: Record::default(),
+            current_streak: None,
+            longest_win_streak: 0,
+            favorite_openings: Vec::new(),
+            average_length: None,
+        };
+        // Games opened on each space, by variant and board size
+        let mut openings: HashMap<(&str, &str), HashMap<usize, usize>> = HashMap::new();
+
+        for game in games {
+            let finish = Finish::of(game);
+            stats.overall.add(finish);
+            if game.team == 'X' {
+                stats.as_x.add(finish);
+            } else {
+                stats.as_o.add(finish);
+            }
+
+            let streak = match stats.current_streak {
+                Some(streak) if streak.finish == finish => streak.games + 1,
+                _ => 1,
+            };
+            stats.current_streak = Some(Streak {
+                finish,
+                games: streak,
+            });
+            if finish == Finish::Win {
+                stats.longest_win_streak = stats.longest_win_streak.max(streak);
+            }
+
+            if let (Some((team, space)), Some(board)) = (game.opening, &game.board) {
+                if team == game.team {
+                    let spaces = openings.entry((&game.variant, board)).or_default();
+                    *spaces.entry(space).or_default() += 1;
+                }
+            }
+        }
+
+        // Lowest space wins a tie, so the answer does not depend on hashing
+        stats.favorite_openings = openings
+            .into_iter()
+            .filter_map(|((variant, board), spaces)| {
+                let (space, games) = spaces
+                    .into_iter()
+                    .max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(&a.0)))?;
+                Some(Opening {
+                    variant: variant.to_string(),
+                    board: board.to_string(),
+                    space,
+                    games,
+                })
+            })
+            .collect();
+        stats.favorite_openings.sort_by(|a, b| {
+            b.games
+                .cmp(&a.games)
+                .then_with(|| (&a.variant, &a.board).cmp(&(&b.variant, &b.board)))
+        });
+        if !games.is_empty() {
+            let moves: usize = games.iter().map(|g| g.moves).sum();
+            stats.average_length = Some(moves as f64 / games.len() as f64);
+        }
+        stats
+    }
+}
+
+pub async fn leaderboard(
+    Query(params): Query<LeaderboardParams>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<Ranking>>, StatusCode> {
+    let limit = params
+        .limit
+        .unwrap_or(DEFAULT_LEADERBOARD_SIZE)
+        .min(MAX_LEADERBOARD_SIZE);
+    query(state, move |profiles| profiles.leaderboard(limit))
+        .await
+        .map(Json)
+}
+
+pub async fn player(
+    Path(id): Path<ProfileID>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<PlayerStats>, StatusCode> {
+    let found = query(state, move |profiles| {
+        let ranking = profiles.ranking(id)?;
+        ranking
+            .map(|ranking| Ok((ranking, profiles.games(id)?)))
+            .transpose()
+    })
+    .await?;
+    let (ranking, games) = found.ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Json(PlayerStats::new(ranking, &games)))
+}
+
+/// Runs `f` against the profiles. SQLite blocks, so it runs on a blocking
+/// thread.
+async fn query<T, F>(state: Arc<AppState>, f: F) -> Result<T, StatusCode>
+where
+    T: Send + 'static,
+    F: FnOnce(&Profiles) -> Result<T, String> + Send + 'static,
+{
+    tokio::task::spawn_blocking(move || f(&state.profiles))
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|result| result)
+        .map_err(internal_error)
+}
+
+fn internal_error(e: String) -> StatusCode {
+    error!("Stats: Database error: {}", e);
+    StatusCode::INTERNAL_SERVER_ERROR
+}
@@ -0,0 +1,216 @@
+use crate::game::{now_secs, Event, Snapshot};
+use rusqlite::{params, Connection};
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::error;
+
+/// Games in progress, saved as they change so that they survive a restart.
+/// A game's log only ever grows, so each save adds the new events rather
+/// than writing out the whole game again.
+///
+/// Each room is saved under its own ID rather than its token. A room that
+/// emptied can linger while its token goes to a new room, and the two must
+/// not write over each other.
+#[derive(Debug)]
+pub struct GameStore {
+    conn: Mutex<Connection>,
+}
+
+/// A game as it was last saved.
+#[derive(Debug)]
+pub struct SavedGame {
+    /// Key the room is saved under.
+    pub id: String,
+    pub token: String,
+    pub snapshot: Snapshot,
+}
+
+impl GameStore {
+    /// Opens the database at `path`, creating it if needed. It can be the
+    /// same database as the profiles.
+    pub fn open(path: &str) -> Result<GameStore, String> {
+        let mut conn = Connection::open(path).map_err(|e| e.to_string())?;
+        conn.busy_timeout(Duration::from_secs(5))
+            .map_err(|e| e.to_string())?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS games (
+                id TEXT PRIMARY KEY,
+                token TEXT NOT NULL,
+                snapshot TEXT NOT NULL,
+                updated_at INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS game_events (
+                game TEXT NOT NULL,
+                seq INTEGER NOT NULL,
+                event TEXT NOT NULL,
+                PRIMARY KEY (game, seq)
+            );",
+        )
+        .map_err(|e| e.to_string())?;
+        // Databases made before rooms had their own key saved them by token
+        if conn.prepare("SELECT id FROM games LIMIT 0").is_err() {
+            let tx = conn.transaction().map_err(|e| e.to_string())?;
+            tx.execute_batch(
+                "ALTER TABLE games RENAME TO old_games;
+                CREATE TABLE games (
+                    id TEXT PRIMARY KEY,
+                    token TEXT NOT NULL,
+                    snapshot TEXT NOT NULL,
+                    updated_at INTEGER NOT NULL
+                );
+                INSERT INTO games (id, token, snapshot, updated_at)
+                    SELECT token, token, snapshot, updated_at FROM old_games;
+                DROP TABLE old_games;",
+            )
+            .map_err(|e| e.to_string())?;
+            tx.commit().map_err(|e| e.to_string())?;
+        }
+        if conn
+            .prepare("SELECT game FROM game_events LIMIT 0")
+            .is_err()
+        {
+            conn.execute("ALTER TABLE game_events RENAME COLUMN token TO game", [])
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(GameStore {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    /// Saves `snapshot` of the room `token` under `id`, with the game's
+    /// events from number `since` on. The rest of the snapshot is small and
+    /// saved whole. Saving from 0 replaces whatever log was saved before.
+    pub fn save(
+        &self,
+        id: &str,
+        token: &str,
+        mut snapshot: Snapshot,
+        since: usize,
+    ) -> Result<(), String> {
+        let events = std::mem::take(&mut snapshot.events);
+        let json = serde_json::to_string(&snapshot).map_err(|e| e.to_string())?;
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+        tx.execute(
+            "INSERT INTO games (id, token, snapshot, updated_at) VALUES (?1, ?2, ?3, ?4)
+            ON CONFLICT (id) DO UPDATE SET snapshot = ?3, updated_at = ?4",
+            params![id, token, json, now_secs()],
+        )
+        .map_err(|e| e.to_string())?;
+        if since == 0 {
+            tx.execute("DELETE FROM game_events WHERE game = ?1", params![id])
+                .map_err(|e| e.to_string())?;
+        }
+        for (seq, event) in (since..).zip(&events) {
+            let json = serde_json::to_string(event).map_err(|e| e.to_string())?;
+            tx.execute(
+                "INSERT INTO game_events (game, seq, event) VALUES (?1, ?2, ?3)",
+                params![id, seq, json],
+            )
+            .map_err(|e| e.to_string())?;
+        }
+        tx.commit().map_err(|e| e.to_string())
+    }
+
+    pub fn delete(&self, id: &str) -> Result<(), String> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+        tx.execute("DELETE FROM game_events WHERE game = ?1", params![id])
+            .map_err(|e| e.to_string())?;
+        tx.execute("DELETE FROM games WHERE id = ?1", params![id])
+            .map_err(|e| e.to_string())?;
+        tx.commit().map_err(|e| e.to_string())
+    }
+
+    /// Every saved game. Games that can no longer be read, say after an
+    /// incompatible upgrade, are logged and left out.
+    pub fn load_all(&self) -> Result<Vec<SavedGame>, String> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn
+            .prepare("SELECT id, token, snapshot FROM games ORDER BY token, id")
+            .map_err(|e| e.to_string())?;
+        let rows = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .map_err(|e| e.to_string())?
+            .collect::<Result<Vec<(String, String, String)>, _>>()
+            .map_err(|e| e.to_string())?;
+
+        let mut events = conn
+            .prepare("SELECT event FROM game_events WHERE game = ?1 ORDER BY seq")
+            .map_err(|e| e.to_string())?;
+        let mut games = Vec::new();
+        for (id, token, json) in rows {
+            let logged = events
+                .query_map(params![id], |row| row.get(0))
+                .map_err(|e| e.to_string())?
+                .collect::<Result<Vec<String>, _>>()
+                .map_err(|e| e.to_string())?;
+            match read_snapshot(&json, &logged) {
+                Ok(snapshot) => games.push(SavedGame {
+                    id,
+                    token,
+                    snapshot,
+                }),
+                Err(e) => error!("Store: Could not read game {}: {}", token, e),
+            }
+        }
+        Ok(games)
+    }
+}
+
+/// Puts a saved game back together from its snapshot and logged events.
+/// Games saved before events were logged separately have them all in the
+/// snapshot.
+fn read_snapshot(json: &str, logged: &[String]) -> Result<Snapshot, serde_json::Error> {
+    let mut snapshot: Snapshot = serde_json::from_str(json)?;
+    for event in logged {
+        snapshot.events.push(serde_json::from_str::<Event>(event)?);
+    }
+    Ok(snapshot)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{Config, FromBrowser, Game, Target};
+
+    #[test]
+    fn saves_add_to_the_log_of_each_room() {
+        let store = GameStore::open(":memory:").unwrap();
+        let (mut game, _) = Game::new("room".to_string(), Config::default());
+        let x = game.add_player("A".to_string()).unwrap().id;
+        game.add_player("B".to_string()).unwrap();
+        store.save("first", "room", game.snapshot(0), 0).unwrap();
+
+        let saved = game.events().len();
+        let move_to = FromBrowser::Move(Target::space(4));
+        game.handle_msg(x, move_to).unwrap();
+        store
+            .save("first", "room", game.snapshot(saved), saved)
+            .unwrap();
+        // Another room under the same token is kept apart
+        let (other, _) = Game::new("room".to_string(), Config::default());
+        store.save("second", "room", other.snapshot(0), 0).unwrap();
+
+        let loaded = store.load_all().unwrap();
+        assert_eq!(loaded.len(), 2);
+        let first = loaded.iter().find(|g| g.id == "first").unwrap();
+        assert_eq!(first.token, "room");
+        assert_eq!(first.snapshot.events.len(), game.events().len());
+        let (restored, _) = Game::restore(first.token.clone(), first.snapshot.clone());
+        assert_eq!(restored.state.board, game.state.board);
+
+        // Saving from the start replaces the log
+        store.save("first", "room", game.snapshot(0), 0).unwrap();
+        let loaded = store.load_all().unwrap();
+        let first = loaded.iter().find(|g| g.id == "first").unwrap();
+        assert_eq!(first.snapshot.events.len(), game.events().len());
+
+        store.delete("first").unwrap();
+        let loaded = store.load_all().unwrap();
+        assert_eq!(
+            loaded.iter().map(|g| g.id.as_str()).collect::<Vec<_>>(),
+            ["second"]
+        );
+    }
+}
@@ -1,18 +1,116 @@
 interface GameState {
     turn: Team;
-    winner: "Draw" | { Win: Team } | null;
+    winner: EndState;
     players: Player[];
-    board: Square[];
+    config: Config;
+    board: Board;
+    sub_boards: SubBoards | null;
+    moves: MoveRecord[];
+    undo_request: PlayerID | null;
+    rematch_offer: Offer | null;
+    draw_offer: PlayerID | null;
+    clock: Clock | null;
+    series: Series | null;
+    spectators: Spectator[];
     chat: ChatMessage[];
 }
 
+interface Config {
+    variant: Variant;
+    width: number;
+    height: number;
+    depth: number;
+    win_length: number;
+    boards: number;
+    misere: boolean;
+    hints: boolean;
+    time_control: TimeControl | null;
+    early_draw: EarlyDraw;
+    best_of: number | null;
+    spectator_chat: boolean;
+    public: boolean;
+}
+
+interface Series {
+    best_of: number;
+    games: { started: PlayerID; winner: PlayerID | null }[];
+    finished: boolean;
+    winner: PlayerID | null;
+}
+
+type EarlyDraw = "Never" | "NoLines" | "Forced";
+
+type TimeControl =
+    | { PerMove: { seconds: number } }
+    | { Total: { seconds: number; increment: number } };
+
+interface Clock {
+    /** Milliseconds left as of `started`. */
+    x: number;
+    o: number;
+    started: number | null;
+}
+
+type Variant = "Classic" | "Ultimate" | "Gravity" | "Notakto" | "Qubic";
+
+interface Board {
+    width: number;
+    height: number;
+    /** Layers of `width * height` cells, 1 unless the board is 3D. */
+    depth: number;
+    cells: Square[];
+    last_move: number | null;
+}
+
+interface SubBoards {
+    results: EndState[];
+    forced: number | null;
+}
+
+type EndState =
+    | { Draw: { reason: EndReason } }
+    | { Win: { team: Team; reason: EndReason; lines: number[][] } }
+    | null;
+
+type EndReason =
+    | "Line"
+    | "BoardFull"
+    | "Resignation"
+    | "Agreement"
+    | "Timeout"
+    | "NoLines"
+    | "Forced";
+
+interface Offer {
+    from: PlayerID;
+    /** Milliseconds since the Unix epoch. */
+    expires: number;
+}
+
+interface MoveRecord {
+    number: number;
+    player_id: PlayerID;
+    team: Team;
+    space: number;
+    /** Milliseconds since the Unix epoch. */
+    time: number;
+}
+
 interface Player {
     id: PlayerID;
     team: Team;
     name: string;
     wins: number;
+    bot: Difficulty | null;
+    /** When the seat is given up unless the player reconnects. */
+    disconnected: number | null;
+    /** Profile the player's games are rated under. */
+    profile: number | null;
+    rating: number | null;
 }
 
+type Difficulty = "easy" | "medium" | "hard";
+
 type PlayerID = number;
 
 type Team = "X" | "O";
@@ -26,7 +124,30 @@ interface ChatMessage {
 
 type System = "System";
 
-type ChatMessageSource = PlayerSource | System;
+type ChatMessageSource = PlayerSource | SpectatorSource | System;
 interface PlayerSource {
     Player: number;
 }
+interface SpectatorSource {
+    Spectator: number;
+}
+
+/** A public game as listed in the lobby. */
+interface Room {
+    token: string;
+    variant: Variant;
+    players: string[];
+    open_seats: number;
+    spectators: number;
+}
+
+interface Spectator {
+    id: PlayerID;
+    name: string;
+}
+
+interface Analysis {
+    best: number;
+    moves: { space: number; outcome: "Win" | "Draw" | "Loss" }[];
+    exact: boolean;
+}